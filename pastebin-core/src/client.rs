@@ -0,0 +1,307 @@
+use crate::{
+    ChannelInfo, ChannelSettings, Entry, EntryUpdate, ErrorBody, NewEntry, Revision, SearchHit,
+    Ulid, ENCRYPTED_HEADER, PASSWORD_HEADER,
+};
+use reqwest::{header::CONTENT_TYPE, Client, Method, RequestBuilder, Response, StatusCode};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("request failed")]
+    Request(#[from] reqwest::Error),
+    #[error("server responded with {status}{}", message_suffix(.message))]
+    Status { status: StatusCode, message: String },
+}
+
+fn message_suffix(message: &str) -> String {
+    if message.is_empty() {
+        String::new()
+    } else {
+        format!(": {}", message)
+    }
+}
+
+/// Contents of an entry, see [`PastebinClient::content`].
+#[derive(Debug, Clone)]
+pub struct Content {
+    pub data: Vec<u8>,
+    /// The contents are ciphertext encrypted by the client that pasted them.
+    pub encrypted: bool,
+}
+
+/// Async client for the HTTP API of a pastebin server.
+///
+/// [`paste`](Self::paste) and [`copy`](Self::copy) use the default channel unless the
+/// client was pointed at another one with [`channel`](Self::channel).
+#[derive(Debug, Clone)]
+pub struct PastebinClient {
+    http: Client,
+    base: String,
+    channel: Option<String>,
+    token: Option<String>,
+    password: Option<String>,
+}
+
+impl PastebinClient {
+    /// Creates a client for the server at `base`, e.g. `http://localhost:3000`.
+    pub fn new(base: impl Into<String>) -> Self {
+        Self::with_client(base, Client::new())
+    }
+
+    /// Creates a client that sends its requests through `http`.
+    pub fn with_client(base: impl Into<String>, http: Client) -> Self {
+        let mut base = base.into();
+        while base.ends_with('/') {
+            base.pop();
+        }
+        PastebinClient {
+            http,
+            base,
+            channel: None,
+            token: None,
+            password: None,
+        }
+    }
+
+    /// Returns a client that authenticates with the bearer `token`.
+    pub fn token(&self, token: impl Into<String>) -> Self {
+        PastebinClient {
+            token: Some(token.into()),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a client that reads protected entries with `password`.
+    pub fn password(&self, password: impl Into<String>) -> Self {
+        PastebinClient {
+            password: Some(password.into()),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a client that pastes to and copies from the channel `name`.
+    pub fn channel(&self, name: impl Into<String>) -> Self {
+        PastebinClient {
+            channel: Some(name.into()),
+            ..self.clone()
+        }
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    /// Adds `entry` to the clipboard and returns it as stored by the server.
+    pub async fn paste(&self, entry: &NewEntry) -> Result<Entry> {
+        let response = self
+            .request(Method::POST, self.channel_url("/paste"))
+            .json(entry)
+            .send()
+            .await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Uploads the file `data` as a new entry, its metadata is taken from `entry`
+    /// whose `data` is ignored.
+    pub async fn upload(&self, entry: &NewEntry, data: Vec<u8>) -> Result<Entry> {
+        let mut query = vec![];
+        if let Some(filename) = &entry.filename {
+            query.push(("filename", filename.clone()));
+        }
+        if let Some(source) = &entry.source {
+            query.push(("source", source.clone()));
+        }
+        if let Some(language) = &entry.language {
+            query.push(("language", language.clone()));
+        }
+        if let Some(ttl) = entry.ttl {
+            query.push(("ttl", ttl.to_string()));
+        }
+        if entry.burn_after_read {
+            query.push(("burn_after_read", "true".to_owned()));
+        }
+        let mut request = self.request(Method::POST, self.channel_url("/upload"));
+        if let Some(password) = &entry.password {
+            request = request.header(PASSWORD_HEADER, password);
+        }
+        let content_type = entry
+            .content_type
+            .as_deref()
+            .unwrap_or("application/octet-stream");
+
+        let response = request
+            .query(&query)
+            .header(CONTENT_TYPE, content_type)
+            .body(data)
+            .send()
+            .await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Fetches all entries of the clipboard, oldest first.
+    pub async fn copy(&self) -> Result<Vec<Entry>> {
+        let response = self
+            .request(Method::GET, self.channel_url("/copy"))
+            .send()
+            .await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Finds entries by the words in their data and metadata, in the client's channel
+    /// if it was given one and in all channels otherwise.
+    pub async fn search(&self, query: &str) -> Result<Vec<SearchHit>> {
+        let mut request = self
+            .request(Method::GET, self.url("/search"))
+            .query(&[("q", query)]);
+        if let Some(channel) = &self.channel {
+            request = request.query(&[("channel", channel)]);
+        }
+        let response = request.send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Fetches the entry with the given `id`.
+    pub async fn get(&self, id: Ulid) -> Result<Entry> {
+        let response = self
+            .read_request(self.url(&format!("/entries/{}", id)))
+            .send()
+            .await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Fetches the contents of the entry with the given `id`, the only way to get
+    /// at the bytes of an uploaded file.
+    pub async fn content(&self, id: Ulid) -> Result<Content> {
+        let response = self
+            .read_request(self.url(&format!("/entries/{}/content", id)))
+            .send()
+            .await?;
+        let response = check_status(response).await?;
+        let encrypted = response.headers().contains_key(ENCRYPTED_HEADER);
+        Ok(Content {
+            data: response.bytes().await?.into(),
+            encrypted,
+        })
+    }
+
+    /// Replaces the contents of the entry with the given `id` with a new revision.
+    pub async fn revise(&self, id: Ulid, update: &EntryUpdate) -> Result<Entry> {
+        let mut request = self
+            .request(Method::PUT, self.url(&format!("/entries/{}", id)))
+            .json(update);
+        if let Some(password) = &self.password {
+            request = request.header(PASSWORD_HEADER, password);
+        }
+        let response = request.send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Fetches the revisions of the entry with the given `id`, oldest first.
+    pub async fn revisions(&self, id: Ulid) -> Result<Vec<Revision>> {
+        let response = self
+            .read_request(self.url(&format!("/entries/{}/revisions", id)))
+            .send()
+            .await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// A unified diff between two revisions of the entry with the given `id`, by
+    /// default between the latest one and the one before it.
+    pub async fn diff(&self, id: Ulid, from: Option<u32>, to: Option<u32>) -> Result<String> {
+        let mut request = self.read_request(self.url(&format!("/entries/{}/diff", id)));
+        if let Some(from) = from {
+            request = request.query(&[("from", from)]);
+        }
+        if let Some(to) = to {
+            request = request.query(&[("to", to)]);
+        }
+        let response = request.send().await?;
+        Ok(check_status(response).await?.text().await?)
+    }
+
+    /// Removes the entry with the given `id` from the clipboard.
+    pub async fn delete(&self, id: Ulid) -> Result<()> {
+        let response = self
+            .request(Method::DELETE, self.url(&format!("/entries/{}", id)))
+            .send()
+            .await?;
+        check_status(response).await?;
+        Ok(())
+    }
+
+    /// Pins or unpins the entry with the given `id`, pinned entries are never evicted.
+    pub async fn pin(&self, id: Ulid, pinned: bool) -> Result<Entry> {
+        let method = if pinned { Method::PUT } else { Method::DELETE };
+        let response = self
+            .request(method, self.url(&format!("/entries/{}/pin", id)))
+            .send()
+            .await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Lists all channels of the server.
+    pub async fn channels(&self) -> Result<Vec<ChannelInfo>> {
+        let response = self
+            .request(Method::GET, self.url("/channels"))
+            .send()
+            .await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Creates the channel `name` or changes its settings.
+    pub async fn configure_channel(
+        &self,
+        name: &str,
+        settings: &ChannelSettings,
+    ) -> Result<ChannelInfo> {
+        let response = self
+            .request(Method::PUT, self.url(&format!("/admin/channels/{}", name)))
+            .json(settings)
+            .send()
+            .await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    fn request(&self, method: Method, url: String) -> RequestBuilder {
+        let request = self.http.request(method, url);
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// A `GET` of a single entry, with the password if one was set.
+    fn read_request(&self, url: String) -> RequestBuilder {
+        let request = self.request(Method::GET, url);
+        match &self.password {
+            Some(password) => request.header(PASSWORD_HEADER, password),
+            None => request,
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+
+    fn channel_url(&self, path: &str) -> String {
+        match &self.channel {
+            Some(name) => format!("{}/c/{}{}", self.base, name, path),
+            None => self.url(path),
+        }
+    }
+}
+
+async fn check_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    let message = match serde_json::from_str::<ErrorBody>(&body) {
+        Ok(body) => body.error,
+        Err(_) => body.trim().to_owned(),
+    };
+    Err(Error::Status { status, message })
+}
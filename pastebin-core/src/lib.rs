@@ -0,0 +1,220 @@
+//! Types shared between `pastebin-server` and its clients.
+//!
+//! With the default `client` feature this crate also provides
+//! [`PastebinClient`], an async client for the server's HTTP API.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "client")]
+mod client;
+
+#[cfg(feature = "client")]
+pub use client::{Content, Error, PastebinClient};
+pub use ulid::Ulid;
+
+/// Content type of entries pasted without one.
+pub const DEFAULT_CONTENT_TYPE: &str = "text/plain";
+
+/// Header carrying the password of a protected entry.
+pub const PASSWORD_HEADER: &str = "x-paste-password";
+
+/// Response header set on the contents of client-side encrypted entries.
+pub const ENCRYPTED_HEADER: &str = "x-paste-encrypted";
+
+/// Request body of `/paste`.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct NewEntry {
+    pub data: String,
+    /// MIME type of `data`, [`DEFAULT_CONTENT_TYPE`] if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// Free form label of where the entry was pasted from, e.g. a hostname.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Name of the file the data was read from, used when it is downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    /// Language to highlight the data as, e.g. `rust`, detected if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Seconds after which the entry expires, the server default if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u64>,
+    /// Delete the entry once it was fetched through `/entries/{id}`, it is never
+    /// listed by `/copy` or pushed to subscribers.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub burn_after_read: bool,
+    /// Password needed to read the entry, see [`PASSWORD_HEADER`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// `data` was encrypted by the client, the server cannot read it.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub encrypted: bool,
+    /// Never evict the entry, see `PUT /entries/{id}/pin`.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub pinned: bool,
+}
+
+/// Request body of `PUT /entries/{id}`, the contents of a new revision of an entry.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct EntryUpdate {
+    pub data: String,
+    /// New MIME type of `data`, the one of the previous revision if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// New language to highlight the data as, the one of the previous revision if
+    /// not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// A single clipboard entry, as returned by `/paste`, `/copy` and `/entries/{id}`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Server assigned, sorts in the order the entries were pasted.
+    pub id: Ulid,
+    pub data: String,
+    /// Set by the server when the entry was pasted.
+    pub created_at: DateTime<Utc>,
+    /// Length of the contents in bytes.
+    pub size: usize,
+    pub content_type: String,
+    pub source: Option<String>,
+    pub filename: Option<String>,
+    pub language: Option<String>,
+    /// The contents are an uploaded file, `data` is empty and the bytes are served
+    /// by `/entries/{id}/content`.
+    #[serde(default)]
+    pub blob: bool,
+    /// The entry is no longer returned after this point in time.
+    pub expires_at: Option<DateTime<Utc>>,
+    pub burn_after_read: bool,
+    /// The entry needs a password to be read, listings only show it without its
+    /// `data`, `filename` and `language`.
+    #[serde(default)]
+    pub protected: bool,
+    /// `data` is ciphertext only the clients holding the key can decrypt.
+    #[serde(default)]
+    pub encrypted: bool,
+    /// The entry is never evicted and does not count against the capacity of its
+    /// channel, only against its pin quota.
+    #[serde(default)]
+    pub pinned: bool,
+    /// Number of the current revision, see `PUT /entries/{id}`.
+    #[serde(default = "first_revision")]
+    pub revision: u32,
+    /// When the current revision was made, unset for entries never revised.
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+fn first_revision() -> u32 {
+    1
+}
+
+/// A version of an entry, as listed by `/entries/{id}/revisions`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Revision {
+    /// Counts up from 1, the entry as returned by `/entries/{id}` is its latest revision.
+    pub revision: u32,
+    pub created_at: DateTime<Utc>,
+    /// Length of `data` in bytes.
+    pub size: usize,
+    pub content_type: String,
+    pub language: Option<String>,
+    /// `data` is ciphertext, like the entry it belongs to.
+    #[serde(default)]
+    pub encrypted: bool,
+    pub data: String,
+}
+
+/// Pushed by `/subscribe` and `/ws` for every entry pasted to `channel`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub channel: String,
+    pub entry: Entry,
+}
+
+/// An entry found by `/search`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct SearchHit {
+    pub channel: String,
+    pub entry: Entry,
+    /// Excerpt of the data of the entry around the first match.
+    pub snippet: String,
+    /// Byte ranges of the matches in `snippet`.
+    pub highlights: Vec<(usize, usize)>,
+}
+
+/// A named clipboard, as listed by `/channels`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ChannelInfo {
+    pub name: String,
+    /// Number of entries the channel holds before evicting the oldest.
+    pub capacity: usize,
+    /// Number of entries currently in the channel.
+    pub entries: usize,
+    /// Total size of the entries in bytes.
+    #[serde(default)]
+    pub bytes: usize,
+    /// Total size the entries may have before the oldest are evicted, unlimited if unset.
+    #[serde(default)]
+    pub max_bytes: Option<usize>,
+    /// Number of pinned entries in the channel.
+    #[serde(default)]
+    pub pins: usize,
+    /// Number of entries that may be pinned.
+    #[serde(default)]
+    pub max_pins: usize,
+}
+
+/// Request body of `PUT /admin/channels/{name}`.
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ChannelSettings {
+    /// New capacity of the channel, the server default for new channels if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capacity: Option<usize>,
+}
+
+/// Body of every error response of the server.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct ErrorBody {
+    pub error: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRY: &str = r#"{
+        "id": "01H8XGJWBWBAQ4Z2CDX5V0N6KM",
+        "data": "hello",
+        "created_at": "2023-08-24T10:00:00Z",
+        "size": 5,
+        "content_type": "text/plain",
+        "burn_after_read": false
+    }"#;
+
+    #[test]
+    fn entries_missing_required_fields_are_rejected() {
+        let entry: Entry = serde_json::from_str(ENTRY).unwrap();
+        assert_eq!(entry.revision, 1);
+        for field in [
+            "id",
+            "created_at",
+            "size",
+            "content_type",
+            "burn_after_read",
+        ] {
+            let mut entry: serde_json::Value = serde_json::from_str(ENTRY).unwrap();
+            entry.as_object_mut().unwrap().remove(field);
+            let entry = serde_json::from_value::<Entry>(entry);
+            assert!(entry.is_err(), "entry without {} was accepted", field);
+        }
+    }
+}
@@ -0,0 +1,575 @@
+use crate::{
+    channels::{self, Channels, DEFAULT_CHANNEL},
+    error::ApiError,
+    extract::{Json, Path, Query},
+    search::Query as SearchQuery,
+};
+use axum::{
+    extract::{OriginalUri, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use pastebin_core::{
+    ChannelSettings, Entry, EntryUpdate, NewEntry, Revision, Ulid, PASSWORD_HEADER,
+};
+use serde::Deserialize;
+use similar::TextDiff;
+use std::{sync::Arc, time::Duration};
+
+/// Longest `source` label accepted on `/paste`.
+const MAX_SOURCE_LEN: usize = 255;
+
+/// Longest file name accepted on `/paste` and `/upload`.
+const MAX_FILENAME_LEN: usize = 255;
+
+const MAX_LANGUAGE_LEN: usize = 64;
+
+const MAX_PASSWORD_LEN: usize = 1024;
+
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+
+const MAX_SEARCH_LIMIT: usize = 100;
+
+pub async fn add_entry(
+    State(channels): State<Arc<Channels>>,
+    Json(entry): Json<NewEntry>,
+) -> Result<impl IntoResponse, ApiError> {
+    paste(&channels, DEFAULT_CHANNEL, entry).await
+}
+
+pub async fn add_channel_entry(
+    State(channels): State<Arc<Channels>>,
+    Path(name): Path<String>,
+    Json(entry): Json<NewEntry>,
+) -> Result<impl IntoResponse, ApiError> {
+    validate_name(&name)?;
+    paste(&channels, &name, entry).await
+}
+
+async fn paste(
+    channels: &Channels,
+    name: &str,
+    entry: NewEntry,
+) -> Result<impl IntoResponse, ApiError> {
+    validate(&entry, channels.max_ttl())?;
+    let entry = channels.paste(name, entry).await?;
+    tracing::debug!("added clipboard entry {}", entry.id);
+    Ok((StatusCode::CREATED, Json(entry)))
+}
+
+pub fn validate(entry: &NewEntry, max_ttl: Option<Duration>) -> Result<(), ApiError> {
+    if let Some(content_type) = &entry.content_type {
+        if content_type.parse::<mime::Mime>().is_err() {
+            return Err(ApiError::bad_request(format!(
+                "invalid content type {:?}",
+                content_type
+            )));
+        }
+    }
+    if let Some(source) = &entry.source {
+        if source.len() > MAX_SOURCE_LEN || source.chars().any(char::is_control) {
+            return Err(ApiError::bad_request(format!(
+                "source must be at most {} bytes without control characters",
+                MAX_SOURCE_LEN
+            )));
+        }
+    }
+    if let Some(filename) = &entry.filename {
+        if filename.is_empty()
+            || filename.len() > MAX_FILENAME_LEN
+            || filename.chars().any(char::is_control)
+        {
+            return Err(ApiError::bad_request(format!(
+                "filename must be 1 to {} bytes without control characters",
+                MAX_FILENAME_LEN
+            )));
+        }
+    }
+    if let Some(language) = &entry.language {
+        if language.is_empty()
+            || language.len() > MAX_LANGUAGE_LEN
+            || language.chars().any(char::is_control)
+        {
+            return Err(ApiError::bad_request(format!(
+                "language must be 1 to {} bytes without control characters",
+                MAX_LANGUAGE_LEN
+            )));
+        }
+    }
+    if let Some(password) = &entry.password {
+        if password.is_empty() || password.len() > MAX_PASSWORD_LEN {
+            return Err(ApiError::bad_request(format!(
+                "password must be 1 to {} bytes",
+                MAX_PASSWORD_LEN
+            )));
+        }
+    }
+    if let Some(ttl) = entry.ttl {
+        let max_ttl = max_ttl.map_or(u64::MAX, |max_ttl| max_ttl.as_secs());
+        if ttl == 0 || ttl > max_ttl {
+            return Err(ApiError::bad_request(format!(
+                "ttl must be between 1 and {} seconds",
+                max_ttl
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    /// Oldest first
+    #[default]
+    Asc,
+    /// Newest first
+    Desc,
+}
+
+/// Filters, order and pagination of `/copy`.
+#[derive(Debug, Default, Deserialize)]
+pub struct CopyQuery {
+    /// Only pinned entries if true, only unpinned ones if false.
+    pinned: Option<bool>,
+    /// Content type without parameters, `type/*` matches every subtype.
+    content_type: Option<String>,
+    source: Option<String>,
+    /// Only entries pasted at or after this point in time.
+    since: Option<DateTime<Utc>>,
+    /// Only entries pasted before this point in time.
+    until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    order: Order,
+    /// Largest number of entries returned, all of them if unset.
+    limit: Option<usize>,
+    /// Id of the last entry of the previous page, only entries after it in `order`
+    /// are returned.
+    cursor: Option<Ulid>,
+}
+
+impl CopyQuery {
+    /// Returns the page of `entries` the query asks for, along with the cursor of
+    /// the next page if there is one.
+    fn apply(&self, entries: Vec<Entry>) -> Result<(Vec<Entry>, Option<Ulid>), ApiError> {
+        if self.limit == Some(0) {
+            return Err(ApiError::bad_request("limit must be at least 1"));
+        }
+
+        let mut entries: Vec<_> = entries
+            .into_iter()
+            .filter(|entry| self.matches(entry))
+            .collect();
+        if self.order == Order::Desc {
+            entries.reverse();
+        }
+        let Some(limit) = self.limit.filter(|&limit| limit < entries.len()) else {
+            return Ok((entries, None));
+        };
+        entries.truncate(limit);
+        let next = entries.last().map(|entry| entry.id);
+        Ok((entries, next))
+    }
+
+    fn matches(&self, entry: &Entry) -> bool {
+        let after_cursor = self.cursor.is_none_or(|cursor| match self.order {
+            Order::Asc => entry.id > cursor,
+            Order::Desc => entry.id < cursor,
+        });
+        after_cursor
+            && self.pinned.is_none_or(|pinned| entry.pinned == pinned)
+            && self
+                .content_type
+                .as_deref()
+                .is_none_or(|content_type| content_type_matches(content_type, entry))
+            && self
+                .source
+                .as_deref()
+                .is_none_or(|source| entry.source.as_deref() == Some(source))
+            && self.since.is_none_or(|since| entry.created_at >= since)
+            && self.until.is_none_or(|until| entry.created_at < until)
+    }
+}
+
+fn content_type_matches(filter: &str, entry: &Entry) -> bool {
+    let essence = entry
+        .content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim();
+    match filter.strip_suffix("/*") {
+        Some(kind) => essence
+            .split_once('/')
+            .is_some_and(|(entry_kind, _)| entry_kind.eq_ignore_ascii_case(kind)),
+        None => essence.eq_ignore_ascii_case(filter),
+    }
+}
+
+pub async fn get_entries(
+    State(channels): State<Arc<Channels>>,
+    OriginalUri(uri): OriginalUri,
+    Query(query): Query<CopyQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    tracing::debug!("fetching clipboard");
+    let entries = channels.default_channel().read().unwrap().get_entries();
+    page(&query, &uri, entries)
+}
+
+pub async fn get_channel_entries(
+    State(channels): State<Arc<Channels>>,
+    Path(name): Path<String>,
+    OriginalUri(uri): OriginalUri,
+    Query(query): Query<CopyQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    validate_name(&name)?;
+    tracing::debug!("fetching channel {}", name);
+    let clipboard = channels
+        .get(&name)
+        .ok_or_else(ApiError::channel_not_found)?;
+    let entries = clipboard.read().unwrap().get_entries();
+    page(&query, &uri, entries)
+}
+
+/// The entries `query` asks for, with a `Link` header pointing to the next page if
+/// there is one.
+fn page(query: &CopyQuery, uri: &Uri, entries: Vec<Entry>) -> Result<impl IntoResponse, ApiError> {
+    let (entries, next) = query.apply(entries)?;
+    let mut headers = HeaderMap::new();
+    if let Some(next) = next {
+        let mut params: Vec<_> = uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|param| !param.is_empty() && !param.starts_with("cursor="))
+            .collect();
+        let cursor = format!("cursor={}", next);
+        params.push(&cursor);
+        let link = format!("<{}?{}>; rel=\"next\"", uri.path(), params.join("&"));
+        headers.insert(
+            header::LINK,
+            link.parse().expect("the link is made of a valid URI"),
+        );
+    }
+    Ok((headers, Json(entries)))
+}
+
+pub async fn get_entry(
+    State(channels): State<Arc<Channels>>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    tracing::debug!("fetching clipboard entry {}", id);
+    let entry = channels.read(id, password(&headers)).await?;
+    entry.map(Json).ok_or_else(ApiError::not_found)
+}
+
+/// Makes the body the latest revision of an entry, the earlier ones are kept.
+pub async fn revise_entry(
+    State(channels): State<Arc<Channels>>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+    Json(update): Json<EntryUpdate>,
+) -> Result<impl IntoResponse, ApiError> {
+    // only the metadata that can change is checked
+    let metadata = NewEntry {
+        content_type: update.content_type.clone(),
+        language: update.language.clone(),
+        ..NewEntry::default()
+    };
+    validate(&metadata, None)?;
+    let entry = channels
+        .revise(id, update, password(&headers))
+        .await?
+        .ok_or_else(ApiError::not_found)?;
+    tracing::debug!(
+        "revised clipboard entry {} to revision {}",
+        id,
+        entry.revision
+    );
+    Ok(Json(entry))
+}
+
+pub async fn get_revisions(
+    State(channels): State<Arc<Channels>>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    tracing::debug!("fetching revisions of clipboard entry {}", id);
+    let revisions = channels.revisions(id, password(&headers)).await?;
+    revisions.map(Json).ok_or_else(ApiError::not_found)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    from: Option<u32>,
+    to: Option<u32>,
+}
+
+/// A unified diff between two revisions of an entry, by default between the latest
+/// one and the one before it.
+pub async fn diff_revisions(
+    State(channels): State<Arc<Channels>>,
+    Path(id): Path<Ulid>,
+    Query(query): Query<DiffQuery>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let revisions = channels
+        .revisions(id, password(&headers))
+        .await?
+        .ok_or_else(ApiError::not_found)?;
+    let diff = diff(&revisions, query.from, query.to)?;
+    Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], diff))
+}
+
+/// A unified diff of `revisions` between `from` and `to`.
+fn diff(revisions: &[Revision], from: Option<u32>, to: Option<u32>) -> Result<String, ApiError> {
+    let latest = revisions.last().map_or(1, |revision| revision.revision);
+    let to = to.unwrap_or(latest);
+    let from = from.unwrap_or(to.saturating_sub(1).max(1));
+    let (from, to) = (revision(revisions, from)?, revision(revisions, to)?);
+
+    let diff = TextDiff::from_lines(&from.data, &to.data)
+        .unified_diff()
+        .header(
+            &format!("revision {}", from.revision),
+            &format!("revision {}", to.revision),
+        )
+        .to_string();
+    Ok(diff)
+}
+
+fn revision(revisions: &[Revision], number: u32) -> Result<&Revision, ApiError> {
+    revisions
+        .iter()
+        .find(|revision| revision.revision == number)
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("revision {} not found", number),
+            )
+        })
+}
+
+pub async fn delete_entry(
+    State(channels): State<Arc<Channels>>,
+    Path(id): Path<Ulid>,
+) -> Result<impl IntoResponse, ApiError> {
+    for clipboard in channels.all() {
+        if clipboard.write().unwrap().remove(id)?.is_some() {
+            tracing::debug!("deleted clipboard entry {}", id);
+            return Ok(StatusCode::NO_CONTENT);
+        }
+    }
+    Err(ApiError::not_found())
+}
+
+pub async fn pin_entry(
+    State(channels): State<Arc<Channels>>,
+    Path(id): Path<Ulid>,
+) -> Result<impl IntoResponse, ApiError> {
+    let entry = channels.pin(id, true)?.ok_or_else(ApiError::not_found)?;
+    tracing::debug!("pinned clipboard entry {}", id);
+    Ok(Json(entry))
+}
+
+pub async fn unpin_entry(
+    State(channels): State<Arc<Channels>>,
+    Path(id): Path<Ulid>,
+) -> Result<impl IntoResponse, ApiError> {
+    let entry = channels.pin(id, false)?.ok_or_else(ApiError::not_found)?;
+    tracing::debug!("unpinned clipboard entry {}", id);
+    Ok(Json(entry))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    q: String,
+    channel: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Finds entries by the words in their data and metadata, `"quoted phrases"` have to
+/// match as a whole.
+pub async fn search(
+    State(channels): State<Arc<Channels>>,
+    Query(params): Query<SearchParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let query = SearchQuery::parse(&params.q)
+        .ok_or_else(|| ApiError::bad_request("query must contain at least one word"))?;
+    let limit = params.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+    if !(1..=MAX_SEARCH_LIMIT).contains(&limit) {
+        return Err(ApiError::bad_request(format!(
+            "limit must be between 1 and {}",
+            MAX_SEARCH_LIMIT
+        )));
+    }
+    if let Some(name) = &params.channel {
+        validate_name(name)?;
+        channels.get(name).ok_or_else(ApiError::channel_not_found)?;
+    }
+
+    tracing::debug!("searching for {:?}", params.q);
+    Ok(Json(channels.search(
+        params.channel.as_deref(),
+        &query,
+        limit,
+    )))
+}
+
+pub async fn list_channels(State(channels): State<Arc<Channels>>) -> impl IntoResponse {
+    Json(channels.list())
+}
+
+pub async fn configure_channel(
+    State(channels): State<Arc<Channels>>,
+    Path(name): Path<String>,
+    Json(settings): Json<ChannelSettings>,
+) -> Result<impl IntoResponse, ApiError> {
+    validate_name(&name)?;
+    if settings.capacity == Some(0) {
+        return Err(ApiError::bad_request("capacity must be at least 1"));
+    }
+
+    let created = channels.configure(&name, settings.capacity)?;
+    tracing::debug!("configured channel {}", name);
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    let info = channels.list().into_iter().find(|info| info.name == name);
+    Ok((status, Json(info)))
+}
+
+/// Password of a protected entry from the [`PASSWORD_HEADER`].
+pub fn password(headers: &HeaderMap) -> Option<&str> {
+    headers.get(PASSWORD_HEADER)?.to_str().ok()
+}
+
+pub fn validate_name(name: &str) -> Result<(), ApiError> {
+    if channels::valid_name(name) {
+        Ok(())
+    } else {
+        Err(ApiError::bad_request(
+            "channel names may only contain ASCII letters, digits, '-' and '_'",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn entries() -> Vec<Entry> {
+        let types = ["text/plain", "image/png", "text/html; charset=utf-8"];
+        (0..5)
+            .map(|i| Entry {
+                created_at: DateTime::from_timestamp(i as i64, 0).unwrap(),
+                content_type: types[i as usize % types.len()].to_owned(),
+                pinned: i % 2 == 0,
+                ..test_support::entry(Ulid::from_parts(i, 0), &i.to_string())
+            })
+            .collect()
+    }
+
+    /// Data of every entry the query returns, following the cursors page by page.
+    fn pages(mut query: CopyQuery) -> Vec<Vec<String>> {
+        let mut pages = vec![];
+        loop {
+            let (page, next) = query.apply(entries()).unwrap();
+            pages.push(page.into_iter().map(|entry| entry.data).collect());
+            let Some(next) = next else { return pages };
+            query.cursor = Some(next);
+        }
+    }
+
+    #[test]
+    fn cursors_page_through_all_entries() {
+        let query = CopyQuery {
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(pages(query), [vec!["0", "1"], vec!["2", "3"], vec!["4"]]);
+
+        let query = CopyQuery {
+            limit: Some(2),
+            order: Order::Desc,
+            ..Default::default()
+        };
+        assert_eq!(pages(query), [vec!["4", "3"], vec!["2", "1"], vec!["0"]]);
+
+        // no cursor for an exactly full last page
+        let query = CopyQuery {
+            limit: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(pages(query).len(), 1);
+        let query = CopyQuery {
+            limit: Some(0),
+            ..Default::default()
+        };
+        assert!(query.apply(entries()).is_err());
+    }
+
+    fn revisions(data: &[&str]) -> Vec<Revision> {
+        (1..)
+            .zip(data)
+            .map(|(revision, data)| Revision {
+                revision,
+                created_at: Utc::now(),
+                size: data.len(),
+                content_type: "text/plain".to_owned(),
+                language: None,
+                encrypted: false,
+                data: data.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diffs_compare_any_two_revisions() {
+        let revisions = revisions(&["a\nb\n", "a\nc\n", "d\nc\n"]);
+        let latest = diff(&revisions, None, None).unwrap();
+        assert!(latest.starts_with("--- revision 2\n+++ revision 3\n"));
+        assert!(latest.contains("-a\n+d\n"));
+
+        let skipped = diff(&revisions, Some(1), Some(3)).unwrap();
+        assert!(skipped.starts_with("--- revision 1\n+++ revision 3\n"));
+        assert!(skipped.contains("-a\n-b\n+d\n+c\n"));
+        assert_eq!(diff(&revisions, Some(2), Some(2)).unwrap(), "");
+
+        let missing = diff(&revisions, Some(1), Some(4)).unwrap_err();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn filters_apply_before_the_limit() {
+        let query = CopyQuery {
+            pinned: Some(true),
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(pages(query), [vec!["0", "2"], vec!["4"]]);
+
+        let query = CopyQuery {
+            content_type: Some("TEXT/*".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(pages(query), [["0", "2", "3"]]);
+        let query = CopyQuery {
+            content_type: Some("text/html".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(pages(query), [["2"]]);
+
+        let query = CopyQuery {
+            since: DateTime::from_timestamp(1, 0),
+            until: DateTime::from_timestamp(3, 0),
+            order: Order::Desc,
+            ..Default::default()
+        };
+        assert_eq!(pages(query), [["2", "1"]]);
+    }
+}
@@ -0,0 +1,329 @@
+use crate::error::ApiError;
+use axum::{
+    extract::State,
+    http::{header, Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use clap::{Subcommand, ValueEnum};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+const TOKEN_PREFIX: &str = "pb_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Fetch entries and subscribe to them
+    Read,
+    /// Paste and delete entries
+    Write,
+    /// Use the `/admin` routes
+    Admin,
+}
+
+impl Scope {
+    /// Scope a request needs to be let through.
+    fn required<B>(request: &Request<B>) -> Scope {
+        let path = request.uri().path();
+        if path.starts_with("/admin/") {
+            Scope::Admin
+        } else if matches!(
+            *request.method(),
+            Method::GET | Method::HEAD | Method::OPTIONS
+        ) {
+            Scope::Read
+        } else if path.starts_with("/view/") {
+            // the form revealing protected and burn-after-read entries only reads them
+            Scope::Read
+        } else {
+            Scope::Write
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Admin => "admin",
+        })
+    }
+}
+
+/// A token as stored in the tokens file, keyed by its name.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct Token {
+    /// Hex encoded SHA-256 of the secret, the secret itself is never stored.
+    hash: String,
+    scopes: Vec<Scope>,
+    created_at: DateTime<Utc>,
+}
+
+/// The API tokens of the server, reloaded whenever the tokens file changes so
+/// `pastebin-server token` takes effect without a restart.
+///
+/// Requests need a token once any exist, or always if tokens are `required`.
+pub struct Tokens {
+    path: Option<PathBuf>,
+    required: bool,
+    loaded: RwLock<Loaded>,
+}
+
+#[derive(Default)]
+struct Loaded {
+    modified: Option<SystemTime>,
+    scopes: HashMap<String, Vec<Scope>>,
+}
+
+impl Tokens {
+    pub fn open(path: Option<PathBuf>, required: bool) -> io::Result<Self> {
+        let tokens = Tokens {
+            path,
+            required,
+            loaded: RwLock::default(),
+        };
+        tokens.refresh()?;
+        match &tokens.path {
+            None => tracing::warn!("no tokens file, every route is public"),
+            Some(_) if tokens.loaded.read().unwrap().scopes.is_empty() => tokens.warn_no_tokens(),
+            Some(_) => {}
+        }
+        Ok(tokens)
+    }
+
+    /// Scopes granted to `secret`, `None` if it is not a valid token.
+    fn scopes(&self, secret: &str) -> Option<Vec<Scope>> {
+        let loaded = self.loaded.read().unwrap();
+        loaded.scopes.get(&hash(secret)).cloned()
+    }
+
+    fn enabled(&self) -> bool {
+        let empty = self.loaded.read().unwrap().scopes.is_empty();
+        !empty || self.required
+    }
+
+    fn refresh(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let modified = match fs::metadata(path) {
+            Ok(metadata) => Some(metadata.modified()?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+        if modified == self.loaded.read().unwrap().modified {
+            return Ok(());
+        }
+
+        let scopes: HashMap<_, _> = read_tokens(path)?
+            .into_values()
+            .map(|token| (token.hash, token.scopes))
+            .collect();
+        let mut loaded = self.loaded.write().unwrap();
+        let had_tokens = !loaded.scopes.is_empty();
+        let has_tokens = !scopes.is_empty();
+        *loaded = Loaded { modified, scopes };
+        drop(loaded);
+        tracing::debug!("loaded tokens from {}", path.display());
+        if had_tokens && !has_tokens {
+            self.warn_no_tokens();
+        } else if !had_tokens && has_tokens && !self.required {
+            tracing::debug!("API tokens exist, requests need one from now on");
+        }
+        Ok(())
+    }
+
+    /// Logs what happens to requests while no tokens exist.
+    fn warn_no_tokens(&self) {
+        if !self.required {
+            tracing::warn!("no API tokens exist, every route is public");
+        } else {
+            tracing::warn!(
+                "no API tokens exist, every request is rejected until one is added with \
+                 `pastebin-server token add`"
+            );
+        }
+    }
+}
+
+/// Middleware rejecting requests without a bearer token of the scope they need.
+pub async fn require_token<B>(
+    State(tokens): State<Arc<Tokens>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if let Err(err) = tokens.refresh() {
+        tracing::error!("failed to reload tokens: {}", err);
+    }
+    if !tokens.enabled() {
+        return next.run(request).await;
+    }
+
+    let scope = Scope::required(&request);
+    let Some(secret) = bearer(&request) else {
+        return unauthorized("missing bearer token");
+    };
+    match tokens.scopes(secret) {
+        Some(scopes) if scopes.contains(&scope) => next.run(request).await,
+        Some(_) => ApiError::forbidden(format!("token lacks the {} scope", scope)).into_response(),
+        None => unauthorized("invalid token"),
+    }
+}
+
+fn unauthorized(message: &str) -> Response {
+    (
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        ApiError::unauthorized(message),
+    )
+        .into_response()
+}
+
+/// Token from the `Authorization` header, never from the URL, which ends up in logs.
+fn bearer<B>(request: &Request<B>) -> Option<&str> {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+fn hash(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TokenCommand {
+    /// Create a token and print its secret, which is not shown again
+    Add {
+        name: String,
+
+        /// Scopes of the token
+        #[arg(short, long = "scope", value_enum, default_values_t = [Scope::Read, Scope::Write])]
+        scopes: Vec<Scope>,
+    },
+    /// List the tokens and their scopes
+    List,
+    /// Revoke a token
+    Revoke { name: String },
+}
+
+/// Runs `command` against the tokens file at `path`.
+pub fn run(command: TokenCommand, path: &Path) -> io::Result<()> {
+    let mut tokens = read_tokens(path)?;
+    match command {
+        TokenCommand::Add { name, mut scopes } => {
+            if tokens.contains_key(&name) {
+                return Err(invalid_input(format!("token {} already exists", name)));
+            }
+
+            let mut secret = [0; 32];
+            OsRng.fill_bytes(&mut secret);
+            let secret = format!("{}{}", TOKEN_PREFIX, hex::encode(secret));
+            scopes.sort();
+            scopes.dedup();
+            let token = Token {
+                hash: hash(&secret),
+                scopes,
+                created_at: Utc::now(),
+            };
+            tokens.insert(name, token);
+            write_tokens(path, &tokens)?;
+            println!("{}", secret);
+        }
+        TokenCommand::List => {
+            for (name, token) in &tokens {
+                let scopes: Vec<_> = token.scopes.iter().map(Scope::to_string).collect();
+                println!("{}\t{}\t{}", name, scopes.join(","), token.created_at);
+            }
+        }
+        TokenCommand::Revoke { name } => {
+            if tokens.remove(&name).is_none() {
+                return Err(invalid_input(format!("no token named {}", name)));
+            }
+            write_tokens(path, &tokens)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_tokens(path: &Path) -> io::Result<BTreeMap<String, Token>> {
+    match fs::read(path) {
+        Ok(json) => serde_json::from_slice(&json).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), err),
+            )
+        }),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(err) => Err(err),
+    }
+}
+
+fn write_tokens(path: &Path, tokens: &BTreeMap<String, Token>) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(tokens)?)?;
+    fs::rename(tmp, path)
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(method: Method, path: &str) -> Scope {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(())
+            .unwrap();
+        Scope::required(&request)
+    }
+
+    #[test]
+    fn routes_need_the_scope_of_what_they_do() {
+        assert_eq!(scope(Method::GET, "/copy"), Scope::Read);
+        assert_eq!(
+            scope(Method::POST, "/view/01H8XGJWBWBAQ4Z2CDX5V0N6KM"),
+            Scope::Read
+        );
+        assert_eq!(scope(Method::POST, "/paste"), Scope::Write);
+        assert_eq!(
+            scope(Method::DELETE, "/entries/01H8XGJWBWBAQ4Z2CDX5V0N6KM"),
+            Scope::Write
+        );
+        assert_eq!(scope(Method::PUT, "/admin/channels/work"), Scope::Admin);
+        assert_eq!(scope(Method::GET, "/admin/channels/work"), Scope::Admin);
+    }
+
+    #[test]
+    fn tokens_are_only_taken_from_the_authorization_header() {
+        let request = Request::builder()
+            .uri("/copy?access_token=pb_secret")
+            .body(())
+            .unwrap();
+        assert_eq!(bearer(&request), None);
+        let request = Request::builder()
+            .uri("/copy")
+            .header(header::AUTHORIZATION, "Bearer pb_secret")
+            .body(())
+            .unwrap();
+        assert_eq!(bearer(&request), Some("pb_secret"));
+    }
+}
@@ -0,0 +1,804 @@
+use crate::{
+    clipboard::{self, AddError, Clipboard, EvictionPolicy, SharedClipboard},
+    config::Config,
+    keys::Keyring,
+    password::{self, PasswordError},
+    search::{self, Query},
+    storage::{BlobStore, DiskBlobs, LogStorage, MemoryBlobs, MemoryStorage, Storage},
+};
+use bytes::Bytes;
+use pastebin_core::{ChannelInfo, Entry, EntryUpdate, Event, NewEntry, Revision, SearchHit, Ulid};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::sync::{broadcast, Mutex};
+use ulid::Generator;
+
+/// Channel behind `/paste` and `/copy`.
+pub const DEFAULT_CHANNEL: &str = "default";
+
+const MAX_NAME_LEN: usize = 64;
+
+/// Number of events a slow subscriber may fall behind before it has to catch up from storage.
+const EVENT_BUFFER: usize = 256;
+
+/// All named clipboards of the server.
+///
+/// With a data directory the default channel is stored in `clipboard.log`, every other
+/// channel in `channels/<name>.log`, capacities set through the admin endpoint in
+/// `channels.json`, and uploaded files in `blobs/<id>`.
+pub struct Channels {
+    clipboards: RwLock<BTreeMap<String, SharedClipboard>>,
+    capacities: RwLock<BTreeMap<String, usize>>,
+    data_dir: Option<PathBuf>,
+    default_capacity: usize,
+    max_bytes: Option<usize>,
+    max_entry_size: Option<usize>,
+    max_pins: usize,
+    eviction: EvictionPolicy,
+    default_ttl: Option<Duration>,
+    max_ttl: Option<Duration>,
+    blobs: Arc<dyn BlobStore>,
+    keys: Option<Arc<Keyring>>,
+    /// Held while an entry is stored and published, so ids are handed out and
+    /// published in ascending order across all channels. Async, so pastes waiting
+    /// for it do not block the runtime while another one is written to disk.
+    ids: Mutex<Generator>,
+    events: broadcast::Sender<Event>,
+}
+
+impl Channels {
+    /// Opens the default channel and every channel found in the data directory,
+    /// encrypting what is written there with `keys`.
+    pub fn open(config: &Config, keys: Option<Arc<Keyring>>) -> io::Result<Self> {
+        let data_dir = config.data_dir.clone();
+        let mut names = vec![DEFAULT_CHANNEL.to_owned()];
+        let mut capacities = BTreeMap::new();
+        let blobs: Arc<dyn BlobStore> = match &data_dir {
+            Some(dir) => Arc::new(DiskBlobs::open(dir.join("blobs"), keys.clone())?),
+            None => Arc::<MemoryBlobs>::default(),
+        };
+        if let Some(dir) = &data_dir {
+            fs::create_dir_all(dir.join("channels"))?;
+            match fs::read(dir.join("channels.json")) {
+                Ok(json) => capacities = serde_json::from_slice(&json)?,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+            for file in fs::read_dir(dir.join("channels"))? {
+                let path = file?.path();
+                if path.extension().is_some_and(|ext| ext == "log") {
+                    if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                        names.push(name.to_owned());
+                    }
+                }
+            }
+        }
+
+        let channels = Channels {
+            clipboards: RwLock::default(),
+            capacities: RwLock::new(capacities),
+            data_dir,
+            default_capacity: config.capacity,
+            max_bytes: config.max_bytes,
+            max_entry_size: config.max_entry_size,
+            max_pins: config.max_pins,
+            eviction: config.eviction,
+            default_ttl: config.default_ttl,
+            max_ttl: config.max_ttl,
+            blobs,
+            keys,
+            ids: Mutex::new(Generator::new()),
+            events: broadcast::channel(EVENT_BUFFER).0,
+        };
+        for name in names.into_iter().filter(|name| valid_name(name)) {
+            let clipboard = channels.open_clipboard(&name)?;
+            channels.clipboards.write().unwrap().insert(name, clipboard);
+        }
+        channels.remove_orphaned_blobs()?;
+        Ok(channels)
+    }
+
+    /// Removes blobs left behind by entries removed while the server was down or
+    /// crashed before the removal was complete, and blobs it crashed while writing.
+    fn remove_orphaned_blobs(&self) -> io::Result<()> {
+        let partial = self.blobs.remove_partial()?;
+        if partial > 0 {
+            tracing::debug!("removed {} partially written blobs", partial);
+        }
+        let referenced: HashSet<_> = self
+            .all()
+            .iter()
+            .flat_map(|clipboard| clipboard.read().unwrap().blob_ids())
+            .collect();
+        for id in self.blobs.ids()? {
+            if !referenced.contains(&id) {
+                tracing::debug!("removing orphaned blob {}", id);
+                self.blobs.remove(id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrites the logs holding records stored unencrypted or with a previous
+    /// storage key, returns how many there were.
+    ///
+    /// Every log is rewritten under a write lock of its channel, which only holds up
+    /// the channel for as long as a compaction does.
+    pub fn reseal_logs(&self) -> io::Result<usize> {
+        let mut resealed = 0;
+        for clipboard in self.all() {
+            if clipboard.write().unwrap().reseal()? {
+                resealed += 1;
+            }
+        }
+        Ok(resealed)
+    }
+
+    /// Re-encrypts the uploaded files stored unencrypted or with a previous storage
+    /// key, returns how many there were.
+    ///
+    /// Every file is rewritten under a read lock of its channel, so reads go on while
+    /// the entry cannot be removed.
+    pub fn reseal_blobs(&self) -> io::Result<usize> {
+        let mut resealed = 0;
+        for clipboard in self.all() {
+            let ids = clipboard.read().unwrap().blob_ids();
+            for id in ids {
+                let clipboard = clipboard.read().unwrap();
+                if clipboard.blob_ids().contains(&id) && self.blobs.reseal(id)? {
+                    resealed += 1;
+                }
+            }
+        }
+        Ok(resealed)
+    }
+
+    pub fn get(&self, name: &str) -> Option<SharedClipboard> {
+        self.clipboards.read().unwrap().get(name).cloned()
+    }
+
+    pub fn default_channel(&self) -> SharedClipboard {
+        self.get(DEFAULT_CHANNEL)
+            .expect("the default channel always exists")
+    }
+
+    /// Returns the channel `name`, creating it with the default capacity if needed.
+    pub fn get_or_create(&self, name: &str) -> io::Result<SharedClipboard> {
+        if let Some(clipboard) = self.get(name) {
+            return Ok(clipboard);
+        }
+
+        let mut clipboards = self.clipboards.write().unwrap();
+        if let Some(clipboard) = clipboards.get(name) {
+            return Ok(clipboard.clone());
+        }
+        let clipboard = self.open_clipboard(name)?;
+        clipboards.insert(name.to_owned(), clipboard.clone());
+        tracing::debug!("created channel {}", name);
+        Ok(clipboard)
+    }
+
+    /// Adds `entry` to the channel `name`, creating it if needed, and notifies subscribers.
+    ///
+    /// Entries without a ttl get the default one, ttls beyond the maximum are capped.
+    pub async fn paste(&self, name: &str, entry: NewEntry) -> Result<Entry, AddError> {
+        self.add(name, entry, None).await
+    }
+
+    /// Like [`paste`](Self::paste), but stores the uploaded file `blob` as the contents
+    /// of the entry instead of its `data`.
+    pub async fn upload(
+        &self,
+        name: &str,
+        entry: NewEntry,
+        blob: Bytes,
+    ) -> Result<Entry, AddError> {
+        self.add(name, entry, Some(blob)).await
+    }
+
+    async fn add(
+        &self,
+        name: &str,
+        mut entry: NewEntry,
+        blob: Option<Bytes>,
+    ) -> Result<Entry, AddError> {
+        let size = blob.as_ref().map_or(entry.data.len(), Bytes::len);
+        if let Some(max) = self.max_entry_size.filter(|&max| size > max) {
+            return Err(AddError::TooLarge { max });
+        }
+        let default_ttl = self.default_ttl.or(self.max_ttl).map(|ttl| ttl.as_secs());
+        entry.ttl = match (entry.ttl.or(default_ttl), self.max_ttl) {
+            (Some(ttl), Some(max_ttl)) => Some(ttl.min(max_ttl.as_secs())),
+            (ttl, _) => ttl,
+        };
+
+        // hashing is slow on purpose, it must not hold up other pastes
+        let password_hash = match entry.password.take() {
+            Some(password) => Some(password::hash(password).await?),
+            None => None,
+        };
+
+        // files are written under a temporary id, so the id lock below that keeps
+        // entries in order only has to wait for renaming them
+        let staged = match blob {
+            Some(blob) => Some(self.stage(blob).await?),
+            None => None,
+        };
+
+        let clipboard = self.get_or_create(name)?;
+        let blobs = self.blobs.clone();
+        let mut ids = self.ids.lock().await;
+        // the generator only fails once 2^80 ids were handed out within one millisecond
+        let id = ids.generate().unwrap_or_else(|_| Ulid::new());
+        // appending to the log may evict and compact it, which syncs to disk
+        let entry = tokio::task::spawn_blocking(move || {
+            if let Some((staged, _)) = staged {
+                if let Err(err) = blobs.rename(staged, id) {
+                    blobs.remove(staged)?;
+                    return Err(err.into());
+                }
+            }
+            let blob = staged.map(|(_, size)| size);
+            let added = clipboard
+                .write()
+                .unwrap()
+                .add(id, entry, blob, password_hash);
+            if added.is_err() && blob.is_some() {
+                blobs.remove(id)?;
+            }
+            added
+        })
+        .await
+        .map_err(io::Error::other)??;
+        if !entry.burn_after_read {
+            // sending only fails if nobody is subscribed
+            let _ = self.events.send(Event {
+                channel: name.to_owned(),
+                entry: clipboard::redact(entry.clone()),
+            });
+        }
+        Ok(entry)
+    }
+
+    /// Writes `blob` to the blob store under a new temporary id on a blocking thread,
+    /// returns the id and the size of the blob.
+    async fn stage(&self, blob: Bytes) -> io::Result<(Ulid, usize)> {
+        let blobs = self.blobs.clone();
+        let staged = Ulid::new();
+        let size = blob.len();
+        tokio::task::spawn_blocking(move || blobs.put(staged, &blob))
+            .await
+            .map_err(io::Error::other)??;
+        Ok((staged, size))
+    }
+
+    /// Subscribes to the entries pasted from now on, and returns the stored entries
+    /// newer than `after` a subscriber missed so far.
+    ///
+    /// Also returns the id the subscription starts after, the newest stored entry if
+    /// `after` is not given, to catch up from if the subscriber lags behind before
+    /// it received anything.
+    pub async fn subscribe(
+        &self,
+        name: Option<&str>,
+        after: Option<Ulid>,
+    ) -> (Vec<Event>, broadcast::Receiver<Event>, Ulid) {
+        let _ids = self.ids.lock().await;
+        let missed = match after {
+            Some(after) => self.events_after(name, after),
+            None => vec![],
+        };
+        let start = after.unwrap_or_else(|| self.newest_id(name).unwrap_or_default());
+        (missed, self.events.subscribe(), start)
+    }
+
+    /// Id of the newest entry in the channel `name`, or in all channels.
+    fn newest_id(&self, name: Option<&str>) -> Option<Ulid> {
+        let clipboards = self.clipboards.read().unwrap();
+        let clipboards = clipboards
+            .iter()
+            .filter(|(channel, _)| name.is_none_or(|name| name == channel.as_str()));
+        clipboards
+            .filter_map(|(_, clipboard)| clipboard.read().unwrap().newest_id())
+            .max()
+    }
+
+    /// Entries newer than `after` in the channel `name`, or in all channels, oldest first.
+    pub fn events_after(&self, name: Option<&str>, after: Ulid) -> Vec<Event> {
+        let mut events: Vec<_> = self
+            .clipboards
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(channel, _)| name.is_none_or(|name| name == channel.as_str()))
+            .flat_map(|(channel, clipboard)| {
+                clipboard
+                    .read()
+                    .unwrap()
+                    .get_entries()
+                    .into_iter()
+                    .filter(|entry| entry.id > after)
+                    .map(|entry| Event {
+                        channel: channel.clone(),
+                        entry,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        events.sort_by_key(|event| event.entry.id);
+        events
+    }
+
+    /// Creates the channel `name` or changes its capacity, returns whether it was created.
+    pub fn configure(&self, name: &str, capacity: Option<usize>) -> io::Result<bool> {
+        let created = self.get(name).is_none();
+        if let Some(capacity) = capacity {
+            let mut capacities = self.capacities.write().unwrap();
+            capacities.insert(name.to_owned(), capacity);
+            if let Some(dir) = &self.data_dir {
+                fs::write(dir.join("channels.json"), serde_json::to_vec(&*capacities)?)?;
+            }
+        }
+
+        let clipboard = self.get_or_create(name)?;
+        if let Some(capacity) = capacity {
+            clipboard.write().unwrap().set_capacity(capacity)?;
+        }
+        Ok(created)
+    }
+
+    pub fn list(&self) -> Vec<ChannelInfo> {
+        self.clipboards
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, clipboard)| {
+                let clipboard = clipboard.read().unwrap();
+                ChannelInfo {
+                    name: name.clone(),
+                    capacity: clipboard.capacity(),
+                    entries: clipboard.len(),
+                    bytes: clipboard.bytes(),
+                    max_bytes: clipboard.max_bytes(),
+                    pins: clipboard.pins(),
+                    max_pins: clipboard.max_pins(),
+                }
+            })
+            .collect()
+    }
+
+    /// Largest ttl entries may be pasted with.
+    pub fn max_ttl(&self) -> Option<Duration> {
+        self.max_ttl
+    }
+
+    /// Removes the expired entries of every channel.
+    pub fn purge_expired(&self) -> io::Result<usize> {
+        let mut purged = 0;
+        for clipboard in self.all() {
+            purged += clipboard.write().unwrap().purge_expired()?;
+        }
+        Ok(purged)
+    }
+
+    /// Every channel, used to look up entries by id.
+    pub fn all(&self) -> Vec<SharedClipboard> {
+        self.clipboards.read().unwrap().values().cloned().collect()
+    }
+
+    /// Looks up the entry with the given `id` without burning it, redacted if protected
+    /// or burn-after-read.
+    pub fn peek(&self, id: Ulid) -> Option<Entry> {
+        let entry = self
+            .all()
+            .iter()
+            .find_map(|clipboard| clipboard.read().unwrap().get(id))?;
+        Some(clipboard::redact(entry))
+    }
+
+    /// Up to `limit` entries of the channel `name`, or of all channels, matching
+    /// `query`, the most matches first and newer entries before older ones.
+    pub fn search(&self, name: Option<&str>, query: &Query, limit: usize) -> Vec<SearchHit> {
+        let mut hits: Vec<_> = self
+            .clipboards
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(channel, _)| name.is_none_or(|name| name == channel.as_str()))
+            .flat_map(|(channel, clipboard)| {
+                let hits = clipboard.read().unwrap().search(query);
+                hits.into_iter()
+                    .map(|(entry, count)| (channel.clone(), entry, count))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        hits.sort_by(|(_, a, a_count), (_, b, b_count)| {
+            b_count.cmp(a_count).then_with(|| b.id.cmp(&a.id))
+        });
+        hits.truncate(limit);
+
+        hits.into_iter()
+            .map(|(channel, entry, _)| {
+                // ciphertext only ever matches by the metadata, it has nothing to show
+                let (snippet, highlights) = if entry.encrypted {
+                    (String::new(), vec![])
+                } else {
+                    search::snippet(&entry.data, query)
+                };
+                SearchHit {
+                    channel,
+                    entry,
+                    snippet,
+                    highlights,
+                }
+            })
+            .collect()
+    }
+
+    /// Pins or unpins the entry with the given `id` in whichever channel holds it,
+    /// returns it redacted if protected or burn-after-read.
+    pub fn pin(&self, id: Ulid, pinned: bool) -> Result<Option<Entry>, AddError> {
+        for clipboard in self.all() {
+            if let Some(entry) = clipboard.write().unwrap().set_pinned(id, pinned)? {
+                return Ok(Some(clipboard::redact(entry)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Looks up the entry with the given `id` in every channel, protected entries
+    /// need their `password`.
+    ///
+    /// Burn-after-read entries are removed by reading them, of concurrent readers
+    /// only the one that removes the entry gets it.
+    pub async fn read(&self, id: Ulid, password: Option<&str>) -> Result<Option<Entry>, ReadError> {
+        self.read_with(id, password, |clipboard, id| Ok(clipboard.get(id)))
+            .await
+    }
+
+    /// Like [`read`](Self::read), but also returns the contents of the entry.
+    pub async fn read_content(
+        &self,
+        id: Ulid,
+        password: Option<&str>,
+    ) -> Result<Option<(Entry, Bytes)>, ReadError> {
+        self.read_with(id, password, Clipboard::content).await
+    }
+
+    async fn read_with<T>(
+        &self,
+        id: Ulid,
+        password: Option<&str>,
+        read: impl Fn(&Clipboard, Ulid) -> io::Result<Option<T>>,
+    ) -> Result<Option<T>, ReadError> {
+        for clipboard in self.all() {
+            let Some(entry) = authorize(&clipboard, id, password).await? else {
+                continue;
+            };
+            if entry.burn_after_read {
+                let mut clipboard = clipboard.write().unwrap();
+                let value = read(&clipboard, id)?;
+                if value.is_some() && clipboard.remove(id)?.is_some() {
+                    tracing::debug!("burned clipboard entry {}", id);
+                }
+                return Ok(value);
+            }
+            let clipboard = clipboard.read().unwrap();
+            let value = read(&clipboard, id)?;
+            if value.is_some() {
+                clipboard.touch(id);
+            }
+            return Ok(value);
+        }
+        Ok(None)
+    }
+
+    /// Makes `update` the latest revision of the entry with the given `id`, protected
+    /// entries need their `password`. Returns the entry redacted if protected.
+    pub async fn revise(
+        &self,
+        id: Ulid,
+        update: EntryUpdate,
+        password: Option<&str>,
+    ) -> Result<Option<Entry>, ReviseError> {
+        if let Some(max) = self.max_entry_size.filter(|&max| update.data.len() > max) {
+            return Err(AddError::TooLarge { max }.into());
+        }
+        for clipboard in self.all() {
+            let Some(entry) = authorize(&clipboard, id, password).await? else {
+                continue;
+            };
+            if entry.blob {
+                return Err(ReviseError::Blob);
+            }
+            if entry.burn_after_read {
+                return Err(ReviseError::BurnAfterRead);
+            }
+            let entry = clipboard.write().unwrap().revise(id, update)?;
+            return Ok(entry.map(clipboard::redact));
+        }
+        Ok(None)
+    }
+
+    /// The kept revisions of the entry with the given `id`, oldest first, protected
+    /// entries need their `password`.
+    ///
+    /// Burn-after-read entries have none, listing them would read them without burning.
+    pub async fn revisions(
+        &self,
+        id: Ulid,
+        password: Option<&str>,
+    ) -> Result<Option<Vec<Revision>>, ReadError> {
+        for clipboard in self.all() {
+            let Some(entry) = authorize(&clipboard, id, password).await? else {
+                continue;
+            };
+            if entry.burn_after_read {
+                return Ok(None);
+            }
+            let clipboard = clipboard.read().unwrap();
+            clipboard.touch(id);
+            return Ok(clipboard.revisions(id));
+        }
+        Ok(None)
+    }
+
+    fn open_clipboard(&self, name: &str) -> io::Result<SharedClipboard> {
+        let storage: Box<dyn Storage> = match &self.data_dir {
+            Some(dir) if name == DEFAULT_CHANNEL => Box::new(LogStorage::open(
+                dir.join("clipboard.log"),
+                self.keys.clone(),
+            )?),
+            Some(dir) => Box::new(LogStorage::open(
+                dir.join("channels").join(format!("{}.log", name)),
+                self.keys.clone(),
+            )?),
+            None => Box::<MemoryStorage>::default(),
+        };
+        let capacity = self
+            .capacities
+            .read()
+            .unwrap()
+            .get(name)
+            .copied()
+            .unwrap_or(self.default_capacity);
+        Ok(Arc::new(RwLock::new(Clipboard::new(
+            storage,
+            self.blobs.clone(),
+            capacity,
+            self.max_bytes,
+            self.max_pins,
+            self.eviction,
+        )?)))
+    }
+}
+
+/// Why an entry could not be read.
+#[derive(Debug, thiserror::Error)]
+pub enum ReadError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("entry is password protected")]
+    PasswordRequired,
+    #[error("wrong password")]
+    WrongPassword,
+    #[error(transparent)]
+    Password(#[from] PasswordError),
+}
+
+/// Why an entry could not be revised.
+#[derive(Debug, thiserror::Error)]
+pub enum ReviseError {
+    #[error(transparent)]
+    Read(#[from] ReadError),
+    #[error(transparent)]
+    Add(#[from] AddError),
+    #[error("uploaded files cannot be revised")]
+    Blob,
+    #[error("burn-after-read entries cannot be revised")]
+    BurnAfterRead,
+}
+
+/// The entry `id` if `clipboard` holds it, once the `password` of a protected entry
+/// was checked.
+async fn authorize(
+    clipboard: &SharedClipboard,
+    id: Ulid,
+    password: Option<&str>,
+) -> Result<Option<Entry>, ReadError> {
+    let (entry, password_hash) = {
+        let clipboard = clipboard.read().unwrap();
+        let hash = clipboard.password_hash(id).map(str::to_owned);
+        (clipboard.get(id), hash)
+    };
+    let Some(entry) = entry else {
+        return Ok(None);
+    };
+    // checked without holding the lock, the hash is slow to verify
+    if let Some(hash) = password_hash {
+        match password {
+            None => return Err(ReadError::PasswordRequired),
+            Some(password) => {
+                if !password::verify(password.to_owned(), hash).await? {
+                    return Err(ReadError::WrongPassword);
+                }
+            }
+        }
+    }
+    Ok(Some(entry))
+}
+
+/// Channel names are used in URLs and file names, so they are limited to ASCII
+/// letters, digits, `-` and `_`.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channels() -> Channels {
+        Channels::open(&Config::default(), None).unwrap()
+    }
+
+    fn burning(data: &str) -> NewEntry {
+        NewEntry {
+            data: data.to_owned(),
+            burn_after_read: true,
+            ..NewEntry::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn pinning_does_not_reveal_burn_after_read_entries() {
+        let channels = channels();
+        let entry = channels
+            .paste(DEFAULT_CHANNEL, burning("hunter2"))
+            .await
+            .unwrap();
+
+        let pinned = channels.pin(entry.id, true).unwrap().unwrap();
+        assert!(pinned.pinned);
+        assert_eq!(pinned.data, "");
+        let unpinned = channels.pin(entry.id, false).unwrap().unwrap();
+        assert_eq!(unpinned.data, "");
+        assert_eq!(channels.peek(entry.id).unwrap().data, "");
+
+        let read = channels.read(entry.id, None).await.unwrap().unwrap();
+        assert_eq!(read.data, "hunter2");
+        assert!(channels.read(entry.id, None).await.unwrap().is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_reads_burn_an_entry_exactly_once() {
+        let channels = Arc::new(channels());
+        let entry = channels
+            .paste(DEFAULT_CHANNEL, burning("hunter2"))
+            .await
+            .unwrap();
+
+        let reads: Vec<_> = (0..16)
+            .map(|_| {
+                let channels = channels.clone();
+                tokio::spawn(async move { channels.read(entry.id, None).await.unwrap() })
+            })
+            .collect();
+        let mut found = vec![];
+        for read in reads {
+            found.extend(read.await.unwrap());
+        }
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].data, "hunter2");
+        assert!(channels.peek(entry.id).is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_pastes_are_published_in_id_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            data_dir: Some(dir.path().to_owned()),
+            ..Config::default()
+        };
+        let channels = Arc::new(Channels::open(&config, None).unwrap());
+        let (_, mut receiver, _) = channels.subscribe(None, None).await;
+
+        let pastes: Vec<_> = (0..16)
+            .map(|i| {
+                let channels = channels.clone();
+                let entry = NewEntry {
+                    data: i.to_string(),
+                    ..NewEntry::default()
+                };
+                tokio::spawn(async move { channels.paste(&format!("c{}", i % 4), entry).await })
+            })
+            .collect();
+        for paste in pastes {
+            paste.await.unwrap().unwrap();
+        }
+        let mut ids = vec![];
+        for _ in 0..16 {
+            ids.push(receiver.recv().await.unwrap().entry.id);
+        }
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]), "{ids:?}");
+    }
+
+    #[tokio::test]
+    async fn encrypted_entries_are_found_by_metadata_without_a_snippet() {
+        let channels = channels();
+        let entry = NewEntry {
+            data: "c2VjcmV0IG5vdGVz notes".to_owned(),
+            filename: Some("notes.txt".to_owned()),
+            encrypted: true,
+            ..NewEntry::default()
+        };
+        channels.paste(DEFAULT_CHANNEL, entry).await.unwrap();
+
+        let hits = channels.search(None, &Query::parse("notes").unwrap(), 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snippet, "");
+        assert!(hits[0].highlights.is_empty());
+        let hits = channels.search(None, &Query::parse("c2VjcmV0IG5vdGVz").unwrap(), 10);
+        assert!(hits.is_empty());
+    }
+
+    #[tokio::test]
+    async fn deleted_entries_take_their_revisions_along() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            data_dir: Some(dir.path().to_owned()),
+            ..Config::default()
+        };
+        let channels = Channels::open(&config, None).unwrap();
+        let entry = NewEntry {
+            data: "first draft".to_owned(),
+            ..NewEntry::default()
+        };
+        let entry = channels.paste(DEFAULT_CHANNEL, entry).await.unwrap();
+        for data in ["second draft", "final"] {
+            let update = EntryUpdate {
+                data: data.to_owned(),
+                ..EntryUpdate::default()
+            };
+            channels.revise(entry.id, update, None).await.unwrap();
+        }
+
+        let revisions = channels.revisions(entry.id, None).await.unwrap().unwrap();
+        let revisions: Vec<_> = revisions
+            .iter()
+            .map(|revision| (revision.revision, revision.data.as_str()))
+            .collect();
+        assert_eq!(
+            revisions,
+            [(1, "first draft"), (2, "second draft"), (3, "final")]
+        );
+        assert_eq!(
+            channels
+                .read(entry.id, None)
+                .await
+                .unwrap()
+                .unwrap()
+                .revision,
+            3
+        );
+
+        let clipboard = channels.default_channel();
+        clipboard.write().unwrap().remove(entry.id).unwrap();
+        let log = fs::read_to_string(dir.path().join("clipboard.log")).unwrap();
+        assert!(!log.contains("draft"));
+        drop((clipboard, channels));
+
+        let channels = Channels::open(&config, None).unwrap();
+        assert!(channels.revisions(entry.id, None).await.unwrap().is_none());
+        assert_eq!(channels.default_channel().read().unwrap().len(), 0);
+    }
+}
@@ -0,0 +1,549 @@
+use crate::{
+    password::PasswordError,
+    search::{Query, SearchIndex},
+    storage::{BlobStore, Storage, StoredEntry},
+};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use pastebin_core::{Entry, EntryUpdate, NewEntry, Revision, Ulid, DEFAULT_CONTENT_TYPE};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    io, mem,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+pub type SharedClipboard = Arc<RwLock<Clipboard>>;
+
+/// Revisions kept of every entry, the oldest ones are dropped beyond that.
+const MAX_REVISIONS: usize = 100;
+
+/// Which entry makes room when a clipboard is full, pinned entries are never evicted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum EvictionPolicy {
+    /// The oldest entry
+    #[default]
+    // pinned entries used to be exempt under this policy only, now they are under all
+    #[serde(alias = "keep-pinned")]
+    #[value(alias = "keep-pinned")]
+    Fifo,
+    /// The entry read the longest time ago, by id or as raw content
+    Lru,
+}
+
+/// Why an entry could not be added or pinned.
+#[derive(Debug, thiserror::Error)]
+pub enum AddError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("entries are limited to {max} bytes")]
+    TooLarge { max: usize },
+    #[error("the channel is full of pinned entries")]
+    Full,
+    #[error("channels are limited to {max} pinned entries")]
+    TooManyPins { max: usize },
+    #[error(transparent)]
+    Password(#[from] PasswordError),
+}
+
+pub struct Clipboard {
+    storage: Box<dyn Storage>,
+    blobs: Arc<dyn BlobStore>,
+    capacity: usize,
+    max_bytes: Option<usize>,
+    max_pins: usize,
+    eviction: EvictionPolicy,
+    /// When the entries were last read, for [`EvictionPolicy::Lru`]. Entries that were
+    /// not read since the server started count as read when they were pasted.
+    last_read: Mutex<HashMap<Ulid, DateTime<Utc>>>,
+    index: SearchIndex,
+}
+
+impl Clipboard {
+    /// Creates a clipboard on top of `storage`, evicting stored entries beyond
+    /// `capacity` entries or `max_bytes` in total.
+    ///
+    /// Up to `max_pins` entries may be pinned, they do not count against `capacity`.
+    pub fn new(
+        storage: Box<dyn Storage>,
+        blobs: Arc<dyn BlobStore>,
+        capacity: usize,
+        max_bytes: Option<usize>,
+        max_pins: usize,
+        eviction: EvictionPolicy,
+    ) -> io::Result<Self> {
+        let mut clipboard = Clipboard {
+            storage,
+            blobs,
+            capacity,
+            max_bytes,
+            max_pins,
+            eviction,
+            last_read: Mutex::default(),
+            index: SearchIndex::default(),
+        };
+        for stored in clipboard.storage.entries() {
+            clipboard.index.insert(&stored.entry);
+        }
+        clipboard.evict(0, 0, None)?;
+        Ok(clipboard)
+    }
+
+    /// Stores `entry` under `id`, evicting entries as the eviction policy says if the
+    /// clipboard is full.
+    ///
+    /// Ids must be handed out in ascending order, entries are kept sorted by them.
+    /// `blob` is the size of the contents already put into the blob store for `id`,
+    /// such entries have no `data`. The password of `entry` is ignored, protected
+    /// entries come with the hash of it.
+    pub fn add(
+        &mut self,
+        id: Ulid,
+        entry: NewEntry,
+        blob: Option<usize>,
+        password_hash: Option<String>,
+    ) -> Result<Entry, AddError> {
+        let created_at = Utc::now();
+        let expires_at = entry
+            .ttl
+            .and_then(|ttl| chrono::Duration::from_std(Duration::from_secs(ttl)).ok())
+            .and_then(|ttl| created_at.checked_add_signed(ttl));
+        let entry = Entry {
+            id,
+            created_at,
+            size: blob.unwrap_or(entry.data.len()),
+            content_type: entry
+                .content_type
+                .unwrap_or_else(|| DEFAULT_CONTENT_TYPE.to_owned()),
+            source: entry.source,
+            filename: entry.filename,
+            language: entry.language,
+            blob: blob.is_some(),
+            expires_at,
+            burn_after_read: entry.burn_after_read,
+            protected: password_hash.is_some(),
+            encrypted: entry.encrypted,
+            pinned: entry.pinned,
+            data: entry.data,
+            revision: 1,
+            updated_at: None,
+        };
+        if let Some(max_bytes) = self.max_bytes.filter(|&max| entry.size > max) {
+            return Err(AddError::TooLarge { max: max_bytes });
+        }
+        self.purge_expired()?;
+        if entry.pinned && self.pins() >= self.max_pins {
+            return Err(AddError::TooManyPins { max: self.max_pins });
+        }
+        if !self.evict(usize::from(!entry.pinned), entry.size, None)? {
+            return Err(AddError::Full);
+        }
+        self.storage.push(StoredEntry {
+            entry: entry.clone(),
+            password_hash,
+            revisions: vec![],
+        })?;
+        self.index.insert(&entry);
+        Ok(entry)
+    }
+
+    /// The entry `id` including the contents of protected entries.
+    pub fn get(&self, id: Ulid) -> Option<Entry> {
+        self.live().find(|e| e.id == id).cloned()
+    }
+
+    /// Hash of the password of the entry `id`, if it is protected.
+    pub fn password_hash(&self, id: Ulid) -> Option<&str> {
+        let stored = self.storage.entries().iter().find(|e| e.entry.id == id)?;
+        stored.password_hash.as_deref()
+    }
+
+    /// Ids of the entries whose contents are in the blob store, expired ones included.
+    pub fn blob_ids(&self) -> Vec<Ulid> {
+        let entries = self.storage.entries().iter().map(|e| &e.entry);
+        entries.filter(|e| e.blob).map(|e| e.id).collect()
+    }
+
+    /// The contents of the entry `id`, from the blob store for uploaded files.
+    pub fn content(&self, id: Ulid) -> io::Result<Option<(Entry, Bytes)>> {
+        let Some(entry) = self.get(id) else {
+            return Ok(None);
+        };
+        if !entry.blob {
+            let data = Bytes::from(entry.data.clone());
+            return Ok(Some((entry, data)));
+        }
+        Ok(self.blobs.get(id)?.map(|blob| (entry, blob)))
+    }
+
+    /// Makes `update` the latest revision of the entry `id`, returns it if it is stored.
+    ///
+    /// The revision may evict other entries to fit, never the entry itself.
+    pub fn revise(&mut self, id: Ulid, update: EntryUpdate) -> Result<Option<Entry>, AddError> {
+        let Some(index) = self.position(id) else {
+            return Ok(None);
+        };
+        let mut stored = self.storage.entries()[index].clone();
+        let grown = stored.bytes();
+        let entry = &mut stored.entry;
+        let previous = Revision {
+            data: mem::take(&mut entry.data),
+            ..current_revision(entry)
+        };
+        stored.revisions.push(previous);
+        let dropped = stored.revisions.len().saturating_sub(MAX_REVISIONS - 1);
+        stored.revisions.drain(..dropped);
+
+        let entry = &mut stored.entry;
+        entry.revision += 1;
+        entry.updated_at = Some(Utc::now());
+        entry.size = update.data.len();
+        entry.data = update.data;
+        if let Some(content_type) = update.content_type {
+            entry.content_type = content_type;
+        }
+        if let Some(language) = update.language {
+            entry.language = Some(language);
+        }
+        if let Some(max) = self.max_bytes.filter(|&max| stored.bytes() > max) {
+            return Err(AddError::TooLarge { max });
+        }
+
+        let grown = stored.bytes().saturating_sub(grown);
+        if !self.evict(0, grown, Some(id))? {
+            return Err(AddError::Full);
+        }
+        // eviction never picks the entry, but it may have expired in the meantime
+        let Some(index) = self.position(id) else {
+            return Ok(None);
+        };
+        self.storage.replace(index, stored.clone())?;
+        self.index.remove(id);
+        self.index.insert(&stored.entry);
+        Ok(Some(stored.entry))
+    }
+
+    /// All revisions of the entry `id` that are kept, oldest first, the latest one is
+    /// the entry itself.
+    pub fn revisions(&self, id: Ulid) -> Option<Vec<Revision>> {
+        let stored = &self.storage.entries()[self.position(id)?];
+        let mut revisions = stored.revisions.clone();
+        revisions.push(current_revision(&stored.entry));
+        Some(revisions)
+    }
+
+    /// Entries matching `query` along with how often they do, in no particular order.
+    pub fn search(&self, query: &Query) -> Vec<(Entry, usize)> {
+        let hits = self.index.search(query).into_iter();
+        hits.filter_map(|(id, count)| Some((self.get(id)?, count)))
+            .collect()
+    }
+
+    /// All entries except the burn-after-read ones, oldest first, protected ones redacted.
+    pub fn get_entries(&self) -> Vec<Entry> {
+        self.live()
+            .filter(|e| !e.burn_after_read)
+            .cloned()
+            .map(redact)
+            .collect()
+    }
+
+    /// Id of the newest stored entry, the largest one.
+    pub fn newest_id(&self) -> Option<Ulid> {
+        self.storage.entries().last().map(|e| e.entry.id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.live().count()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Total size of the stored entries and their earlier revisions in bytes.
+    pub fn bytes(&self) -> usize {
+        self.storage.entries().iter().map(StoredEntry::bytes).sum()
+    }
+
+    pub fn max_bytes(&self) -> Option<usize> {
+        self.max_bytes
+    }
+
+    /// Changes the capacity, evicting the entries that no longer fit.
+    pub fn set_capacity(&mut self, capacity: usize) -> io::Result<()> {
+        self.capacity = capacity;
+        self.evict(0, 0, None).map(drop)
+    }
+
+    /// Pins or unpins the entry `id`, returns it if it is stored.
+    ///
+    /// An unpinned entry counts against the capacity again, if the clipboard is full
+    /// the next paste may evict it.
+    pub fn set_pinned(&mut self, id: Ulid, pinned: bool) -> Result<Option<Entry>, AddError> {
+        let Some(index) = self.position(id) else {
+            return Ok(None);
+        };
+        let mut stored = self.storage.entries()[index].clone();
+        if stored.entry.pinned != pinned {
+            if pinned && self.pins() >= self.max_pins {
+                return Err(AddError::TooManyPins { max: self.max_pins });
+            }
+            stored.entry.pinned = pinned;
+            self.storage.replace(index, stored.clone())?;
+        }
+        Ok(Some(stored.entry))
+    }
+
+    /// Number of pinned entries.
+    pub fn pins(&self) -> usize {
+        self.live().filter(|e| e.pinned).count()
+    }
+
+    pub fn max_pins(&self) -> usize {
+        self.max_pins
+    }
+
+    /// Re-encrypts the stored entries with the current storage key if needed, returns
+    /// whether they were encrypted with another key or none.
+    pub fn reseal(&mut self) -> io::Result<bool> {
+        self.storage.reseal()
+    }
+
+    /// Records that the entry `id` was read just now.
+    pub fn touch(&self, id: Ulid) {
+        if self.eviction == EvictionPolicy::Lru {
+            self.last_read.lock().unwrap().insert(id, Utc::now());
+        }
+    }
+
+    /// Removes the entry with the given `id`, returning it if it was stored.
+    pub fn remove(&mut self, id: Ulid) -> io::Result<Option<Entry>> {
+        match self.position(id) {
+            Some(index) => self.discard(index).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Index of the entry `id` in the storage, unless it expired.
+    fn position(&self, id: Ulid) -> Option<usize> {
+        let now = Utc::now();
+        self.storage
+            .entries()
+            .iter()
+            .position(|e| e.entry.id == id && !expired(&e.entry, now))
+    }
+
+    /// Removes all expired entries and returns how many there were.
+    pub fn purge_expired(&mut self) -> io::Result<usize> {
+        let now = Utc::now();
+        let mut purged = 0;
+        while let Some(index) = self
+            .storage
+            .entries()
+            .iter()
+            .position(|e| expired(&e.entry, now))
+        {
+            self.discard(index)?;
+            purged += 1;
+        }
+        Ok(purged)
+    }
+
+    /// Entries that did not expire yet, the sweeper may not have caught up with all of them.
+    fn live(&self) -> impl Iterator<Item = &Entry> {
+        let now = Utc::now();
+        self.storage
+            .entries()
+            .iter()
+            .map(|e| &e.entry)
+            .filter(move |e| !expired(e, now))
+    }
+
+    /// Evicts entries other than `keep` until `entries` more entries of `bytes` in
+    /// total fit, returns false if the policy ran out of entries it may evict first.
+    fn evict(&mut self, entries: usize, bytes: usize, keep: Option<Ulid>) -> io::Result<bool> {
+        while !self.fits(entries, bytes) {
+            let Some(index) = self.victim(keep) else {
+                return Ok(false);
+            };
+            self.discard(index)?;
+        }
+        Ok(true)
+    }
+
+    /// Whether `entries` more unpinned entries of `bytes` in total fit.
+    fn fits(&self, entries: usize, bytes: usize) -> bool {
+        let unpinned = self.storage.entries().iter().filter(|e| !e.entry.pinned);
+        unpinned.count() + entries <= self.capacity
+            && self.max_bytes.is_none_or(|max| self.bytes() + bytes <= max)
+    }
+
+    /// Index of the entry other than `keep` to evict next.
+    fn victim(&self, keep: Option<Ulid>) -> Option<usize> {
+        let mut entries = self
+            .storage
+            .entries()
+            .iter()
+            .map(|e| &e.entry)
+            .enumerate()
+            .filter(|(_, e)| !e.pinned && Some(e.id) != keep);
+        let (index, _) = match self.eviction {
+            EvictionPolicy::Fifo => entries.next()?,
+            EvictionPolicy::Lru => {
+                let last_read = self.last_read.lock().unwrap();
+                let read_at = |e: &Entry| last_read.get(&e.id).copied().unwrap_or(e.created_at);
+                entries.min_by_key(|(_, e)| read_at(e))?
+            }
+        };
+        Some(index)
+    }
+
+    /// Removes the entry at `index` along with its blob, every removal goes through here.
+    fn discard(&mut self, index: usize) -> io::Result<Entry> {
+        let entry = self.storage.remove(index)?.entry;
+        if entry.blob {
+            self.blobs.remove(entry.id)?;
+        }
+        self.last_read.lock().unwrap().remove(&entry.id);
+        self.index.remove(entry.id);
+        Ok(entry)
+    }
+}
+
+fn expired(entry: &Entry, now: DateTime<Utc>) -> bool {
+    entry.expires_at.is_some_and(|expires_at| expires_at <= now)
+}
+
+/// The current contents of `entry` as a revision.
+fn current_revision(entry: &Entry) -> Revision {
+    Revision {
+        revision: entry.revision,
+        created_at: entry.updated_at.unwrap_or(entry.created_at),
+        size: entry.size,
+        content_type: entry.content_type.clone(),
+        language: entry.language.clone(),
+        encrypted: entry.encrypted,
+        data: entry.data.clone(),
+    }
+}
+
+/// Leaves only the metadata of protected and burn-after-read entries that may be
+/// shown without reading them.
+pub fn redact(entry: Entry) -> Entry {
+    if !entry.protected && !entry.burn_after_read {
+        return entry;
+    }
+    Entry {
+        data: String::new(),
+        filename: None,
+        language: None,
+        ..entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryBlobs, MemoryStorage};
+    use std::thread;
+
+    fn clipboard(capacity: usize, max_bytes: Option<usize>, eviction: EvictionPolicy) -> Clipboard {
+        let storage = Box::<MemoryStorage>::default();
+        let blobs = Arc::<MemoryBlobs>::default();
+        Clipboard::new(storage, blobs, capacity, max_bytes, 10, eviction).unwrap()
+    }
+
+    /// Adds an entry holding `data` with the next id, pinned if asked to.
+    fn add(clipboard: &mut Clipboard, data: &str, pinned: bool) -> Result<Entry, AddError> {
+        let id = Ulid::from_parts(clipboard.storage.entries().len() as u64 + 1, 0);
+        let entry = NewEntry {
+            data: data.to_owned(),
+            pinned,
+            ..NewEntry::default()
+        };
+        clipboard.add(id, entry, None, None)
+    }
+
+    fn data(clipboard: &Clipboard) -> Vec<&str> {
+        clipboard.live().map(|e| e.data.as_str()).collect()
+    }
+
+    #[test]
+    fn fifo_evicts_the_oldest_entry() {
+        let mut clipboard = clipboard(2, None, EvictionPolicy::Fifo);
+        for data in ["1", "2", "3"] {
+            add(&mut clipboard, data, false).unwrap();
+        }
+        assert_eq!(data(&clipboard), ["2", "3"]);
+    }
+
+    #[test]
+    fn lru_evicts_the_entry_read_the_longest_time_ago() {
+        let mut clipboard = clipboard(2, None, EvictionPolicy::Lru);
+        let first = add(&mut clipboard, "1", false).unwrap();
+        add(&mut clipboard, "2", false).unwrap();
+        thread::sleep(Duration::from_millis(2));
+        clipboard.touch(first.id);
+        add(&mut clipboard, "3", false).unwrap();
+        assert_eq!(data(&clipboard), ["1", "3"]);
+    }
+
+    #[test]
+    fn byte_budgets_evict_until_the_entry_fits() {
+        let mut clipboard = clipboard(10, Some(10), EvictionPolicy::Fifo);
+        for data in ["aaaa", "bbbb", "cccc"] {
+            add(&mut clipboard, data, false).unwrap();
+        }
+        assert_eq!(data(&clipboard), ["bbbb", "cccc"]);
+        add(&mut clipboard, "dddddddd", false).unwrap();
+        assert_eq!(data(&clipboard), ["dddddddd"]);
+        let err = add(&mut clipboard, "too large!!", false).unwrap_err();
+        assert!(matches!(err, AddError::TooLarge { max: 10 }));
+    }
+
+    #[test]
+    fn pinned_entries_are_never_evicted() {
+        for eviction in [EvictionPolicy::Fifo, EvictionPolicy::Lru] {
+            let mut clipboard = clipboard(1, Some(6), eviction);
+            add(&mut clipboard, "pppp", true).unwrap();
+            add(&mut clipboard, "1", false).unwrap();
+            add(&mut clipboard, "2", false).unwrap();
+            assert_eq!(data(&clipboard), ["pppp", "2"]);
+            let err = add(&mut clipboard, "333", false).unwrap_err();
+            assert!(matches!(err, AddError::Full));
+            assert_eq!(data(&clipboard)[0], "pppp");
+        }
+    }
+
+    #[test]
+    fn expired_entries_are_left_out_before_they_are_purged() {
+        let mut clipboard = clipboard(10, None, EvictionPolicy::Fifo);
+        let expired = add(&mut clipboard, "expired note", false).unwrap();
+        let live = add(&mut clipboard, "live note", false).unwrap();
+        // expire the first entry without the sweeper or another add purging it
+        let mut stored = clipboard.storage.entries()[0].clone();
+        stored.entry.expires_at = Some(Utc::now() - chrono::Duration::seconds(1));
+        clipboard.storage.replace(0, stored).unwrap();
+
+        let ids: Vec<_> = clipboard.get_entries().iter().map(|e| e.id).collect();
+        assert_eq!(ids, [live.id]);
+        assert_eq!(clipboard.len(), 1);
+        assert!(clipboard.get(expired.id).is_none());
+        assert!(clipboard.content(expired.id).unwrap().is_none());
+        let hits = clipboard.search(&Query::parse("note").unwrap());
+        let ids: Vec<_> = hits.iter().map(|(e, _)| e.id).collect();
+        assert_eq!(ids, [live.id]);
+    }
+
+    #[test]
+    fn keep_pinned_is_another_name_for_fifo() {
+        let policy = EvictionPolicy::from_str("keep-pinned", false).unwrap();
+        assert_eq!(policy, EvictionPolicy::Fifo);
+        let policy: EvictionPolicy = serde_json::from_str(r#""keep-pinned""#).unwrap();
+        assert_eq!(policy, EvictionPolicy::Fifo);
+    }
+}
@@ -0,0 +1,376 @@
+use crate::{auth::TokenCommand, clipboard::EvictionPolicy};
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+use std::{
+    env, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_LISTEN: ([u8; 4], u16) = ([0, 0, 0, 0], 3000);
+const DEFAULT_CAPACITY: usize = 10;
+const DEFAULT_TIMEOUT: u64 = 10;
+const DEFAULT_MAX_PINS: usize = 10;
+const DEFAULT_MAX_UPLOAD_SIZE: usize = 16 * 1024 * 1024;
+const DEFAULT_INGEST_TIMEOUT: u64 = 2;
+const DEFAULT_INGEST_MAX_CONNECTIONS: usize = 4;
+const DEFAULT_LOG: &str = "pastebin_server=debug,tower_http=debug";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read {}: {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("failed to parse {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("capacity must be at least 1")]
+    Capacity,
+    #[error("timeout must be at least 1 second")]
+    Timeout,
+    #[error("ttls must be at least 1 second")]
+    Ttl,
+    #[error("default ttl must not exceed the maximum ttl")]
+    DefaultTtl,
+    #[error("max upload size must be at least 1 byte")]
+    MaxUploadSize,
+    #[error("max bytes must be at least 1 byte")]
+    MaxBytes,
+    #[error("max entry size must be at least 1 byte")]
+    MaxEntrySize,
+    #[error("ingest timeout must be at least 1 second")]
+    IngestTimeout,
+    #[error("ingest connection limit must be at least 1")]
+    IngestMaxConnections,
+    #[error("invalid log filter {filter:?}: {reason}")]
+    Log { filter: String, reason: String },
+}
+
+/// An axum server to copy and paste via an online clipboard
+///
+/// Every option can also be set with its `PASTEBIN_*` environment variable or in the
+/// config file, flags take precedence over the environment which takes precedence
+/// over the file.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    /// TOML file to read further options from
+    #[arg(short, long, env = "PASTEBIN_CONFIG")]
+    config: Option<PathBuf>,
+
+    #[command(flatten)]
+    options: Options,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Manage the API tokens instead of running the server
+    #[command(subcommand)]
+    Token(TokenCommand),
+}
+
+#[derive(Debug, Default, clap::Args, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Options {
+    /// Address to listen on [default: 0.0.0.0:3000]
+    #[arg(short, long, env = "PASTEBIN_LISTEN")]
+    listen: Option<SocketAddr>,
+
+    /// Number of entries the clipboard holds [default: 10]
+    #[arg(long, env = "PASTEBIN_CAPACITY")]
+    capacity: Option<usize>,
+
+    /// Total size in bytes of the entries a channel holds, unlimited if unset
+    #[arg(long, env = "PASTEBIN_MAX_BYTES")]
+    max_bytes: Option<usize>,
+
+    /// Largest entry in bytes, only limited by the other limits if unset
+    #[arg(long, env = "PASTEBIN_MAX_ENTRY_SIZE")]
+    max_entry_size: Option<usize>,
+
+    /// Number of pinned entries a channel holds on top of its capacity [default: 10]
+    #[arg(long, env = "PASTEBIN_MAX_PINS")]
+    max_pins: Option<usize>,
+
+    /// Which entry is evicted when a channel is full [default: fifo]
+    #[arg(long, env = "PASTEBIN_EVICTION")]
+    eviction: Option<EvictionPolicy>,
+
+    /// Seconds after which a request or TCP paste times out [default: 10]
+    #[arg(long, env = "PASTEBIN_TIMEOUT")]
+    timeout: Option<u64>,
+
+    /// Seconds after which entries pasted without a ttl expire, never if unset
+    #[arg(long, env = "PASTEBIN_DEFAULT_TTL")]
+    default_ttl: Option<u64>,
+
+    /// Largest ttl in seconds an entry may be pasted with, unlimited if unset
+    #[arg(long, env = "PASTEBIN_MAX_TTL")]
+    max_ttl: Option<u64>,
+
+    /// Largest request body in bytes, limits the size of uploaded files [default: 16 MiB]
+    #[arg(long, env = "PASTEBIN_MAX_UPLOAD_SIZE")]
+    max_upload_size: Option<usize>,
+
+    /// URL the server is reachable at, for the links returned to plain text pastes
+    /// [default: taken from the Host header]
+    #[arg(long, env = "PASTEBIN_PUBLIC_URL")]
+    public_url: Option<String>,
+
+    /// Address of a raw TCP listener taking netcat pastes, disabled if unset
+    ///
+    /// Its pastes go to the default channel without authentication.
+    #[arg(long, env = "PASTEBIN_INGEST_LISTEN")]
+    ingest_listen: Option<SocketAddr>,
+
+    /// Seconds without data after which a TCP paste is complete [default: 2]
+    #[arg(long, env = "PASTEBIN_INGEST_TIMEOUT")]
+    ingest_timeout: Option<u64>,
+
+    /// Open TCP connections allowed per client address [default: 4]
+    #[arg(long, env = "PASTEBIN_INGEST_MAX_CONNECTIONS")]
+    ingest_max_connections: Option<usize>,
+
+    /// Log filter, falls back to RUST_LOG [default: pastebin_server=debug,tower_http=debug]
+    #[arg(long, env = "PASTEBIN_LOG")]
+    log: Option<String>,
+
+    /// Directory to persist the clipboard in, entries are only kept in memory if unset
+    #[arg(long, env = "PASTEBIN_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+
+    /// File holding the API tokens [default: tokens.json in the data directory]
+    #[arg(long, env = "PASTEBIN_TOKENS_FILE")]
+    tokens_file: Option<PathBuf>,
+
+    /// Serve requests without a token while the tokens file given with --tokens-file
+    /// holds none, instead of rejecting them
+    #[arg(long, env = "PASTEBIN_ALLOW_ANONYMOUS")]
+    #[serde(default)]
+    allow_anonymous: bool,
+
+    /// File holding the keys the data directory is encrypted with, as 64 hex digits
+    /// each, the current key first
+    #[arg(long, env = "PASTEBIN_STORAGE_KEY_FILE")]
+    storage_key_file: Option<PathBuf>,
+
+    /// Keys the data directory is encrypted with, comma separated, instead of
+    /// --storage-key-file
+    #[arg(
+        long,
+        env = "PASTEBIN_STORAGE_KEY",
+        hide_env_values = true,
+        conflicts_with = "storage_key_file"
+    )]
+    storage_key: Option<String>,
+}
+
+impl Options {
+    /// Fills every option not set in `self` from `fallback`.
+    fn or(self, fallback: Options) -> Options {
+        Options {
+            listen: self.listen.or(fallback.listen),
+            capacity: self.capacity.or(fallback.capacity),
+            max_bytes: self.max_bytes.or(fallback.max_bytes),
+            max_entry_size: self.max_entry_size.or(fallback.max_entry_size),
+            max_pins: self.max_pins.or(fallback.max_pins),
+            eviction: self.eviction.or(fallback.eviction),
+            timeout: self.timeout.or(fallback.timeout),
+            default_ttl: self.default_ttl.or(fallback.default_ttl),
+            max_ttl: self.max_ttl.or(fallback.max_ttl),
+            max_upload_size: self.max_upload_size.or(fallback.max_upload_size),
+            public_url: self.public_url.or(fallback.public_url),
+            ingest_listen: self.ingest_listen.or(fallback.ingest_listen),
+            ingest_timeout: self.ingest_timeout.or(fallback.ingest_timeout),
+            ingest_max_connections: self
+                .ingest_max_connections
+                .or(fallback.ingest_max_connections),
+            log: self.log.or(fallback.log),
+            data_dir: self.data_dir.or(fallback.data_dir),
+            tokens_file: self.tokens_file.or(fallback.tokens_file),
+            allow_anonymous: self.allow_anonymous || fallback.allow_anonymous,
+            storage_key_file: self.storage_key_file.or(fallback.storage_key_file),
+            storage_key: self.storage_key.or(fallback.storage_key),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub listen: SocketAddr,
+    pub capacity: usize,
+    pub max_bytes: Option<usize>,
+    pub max_entry_size: Option<usize>,
+    pub max_pins: usize,
+    pub eviction: EvictionPolicy,
+    pub timeout: Duration,
+    pub default_ttl: Option<Duration>,
+    pub max_ttl: Option<Duration>,
+    pub max_upload_size: usize,
+    pub public_url: Option<String>,
+    pub ingest_listen: Option<SocketAddr>,
+    pub ingest_timeout: Duration,
+    pub ingest_max_connections: usize,
+    pub log: String,
+    pub data_dir: Option<PathBuf>,
+    pub tokens_file: Option<PathBuf>,
+    /// Whether requests need a token even while no tokens exist.
+    pub require_tokens: bool,
+    pub storage_key_file: Option<PathBuf>,
+    pub storage_key: Option<String>,
+}
+
+impl Config {
+    /// Loads the config from the command line, the environment and the config file,
+    /// along with the command to run instead of the server, if any.
+    pub fn load() -> Result<(Self, Option<Command>), ConfigError> {
+        Config::from_args(Args::parse())
+    }
+
+    fn from_args(args: Args) -> Result<(Self, Option<Command>), ConfigError> {
+        let options = match &args.config {
+            Some(path) => args.options.or(read_file(path)?),
+            None => args.options,
+        };
+        Ok((Config::resolve(options)?, args.command))
+    }
+
+    fn resolve(options: Options) -> Result<Self, ConfigError> {
+        let capacity = options.capacity.unwrap_or(DEFAULT_CAPACITY);
+        if capacity == 0 {
+            return Err(ConfigError::Capacity);
+        }
+
+        if options.max_bytes == Some(0) {
+            return Err(ConfigError::MaxBytes);
+        }
+        if options.max_entry_size == Some(0) {
+            return Err(ConfigError::MaxEntrySize);
+        }
+
+        let timeout = options.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        if timeout == 0 {
+            return Err(ConfigError::Timeout);
+        }
+
+        if options.default_ttl == Some(0) || options.max_ttl == Some(0) {
+            return Err(ConfigError::Ttl);
+        }
+        if let (Some(default_ttl), Some(max_ttl)) = (options.default_ttl, options.max_ttl) {
+            if default_ttl > max_ttl {
+                return Err(ConfigError::DefaultTtl);
+            }
+        }
+
+        let max_upload_size = options.max_upload_size.unwrap_or(DEFAULT_MAX_UPLOAD_SIZE);
+        if max_upload_size == 0 {
+            return Err(ConfigError::MaxUploadSize);
+        }
+
+        let ingest_timeout = options.ingest_timeout.unwrap_or(DEFAULT_INGEST_TIMEOUT);
+        if ingest_timeout == 0 {
+            return Err(ConfigError::IngestTimeout);
+        }
+        let ingest_max_connections = options
+            .ingest_max_connections
+            .unwrap_or(DEFAULT_INGEST_MAX_CONNECTIONS);
+        if ingest_max_connections == 0 {
+            return Err(ConfigError::IngestMaxConnections);
+        }
+
+        let log = options
+            .log
+            .or_else(|| env::var(EnvFilter::DEFAULT_ENV).ok())
+            .unwrap_or_else(|| DEFAULT_LOG.to_owned());
+        if let Err(err) = EnvFilter::try_new(&log) {
+            return Err(ConfigError::Log {
+                filter: log,
+                reason: err.to_string(),
+            });
+        }
+
+        Ok(Config {
+            listen: options.listen.unwrap_or_else(|| DEFAULT_LISTEN.into()),
+            capacity,
+            max_bytes: options.max_bytes,
+            max_entry_size: options.max_entry_size,
+            max_pins: options.max_pins.unwrap_or(DEFAULT_MAX_PINS),
+            eviction: options.eviction.unwrap_or_default(),
+            timeout: Duration::from_secs(timeout),
+            default_ttl: options.default_ttl.map(Duration::from_secs),
+            max_ttl: options.max_ttl.map(Duration::from_secs),
+            max_upload_size,
+            public_url: options
+                .public_url
+                .map(|url| url.trim_end_matches('/').to_owned()),
+            ingest_listen: options.ingest_listen,
+            ingest_timeout: Duration::from_secs(ingest_timeout),
+            ingest_max_connections,
+            log,
+            // only a tokens file given explicitly locks the server before tokens exist
+            require_tokens: options.tokens_file.is_some() && !options.allow_anonymous,
+            tokens_file: options
+                .tokens_file
+                .or_else(|| Some(options.data_dir.as_ref()?.join("tokens.json"))),
+            data_dir: options.data_dir,
+            storage_key_file: options.storage_key_file,
+            storage_key: options.storage_key,
+        })
+    }
+}
+
+#[cfg(test)]
+impl Default for Config {
+    /// The config of a server started without any options.
+    fn default() -> Self {
+        Config::resolve(Options::default()).unwrap()
+    }
+}
+
+fn read_file(path: &Path) -> Result<Options, ConfigError> {
+    let contents = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        path: path.to_owned(),
+        source,
+    })?;
+    toml::from_str(&contents).map_err(|source| ConfigError::Parse {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_override_the_environment_which_overrides_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("pastebin.toml");
+        fs::write(&file, "capacity = 1\ntimeout = 5\nmax_pins = 7\n").unwrap();
+
+        // no other test reads these variables
+        env::set_var("PASTEBIN_CAPACITY", "2");
+        env::set_var("PASTEBIN_TIMEOUT", "6");
+        let args = Args::try_parse_from([
+            "pastebin-server",
+            "--config",
+            file.to_str().unwrap(),
+            "--capacity",
+            "3",
+        ]);
+        env::remove_var("PASTEBIN_CAPACITY");
+        env::remove_var("PASTEBIN_TIMEOUT");
+
+        let (config, _) = Config::from_args(args.unwrap()).unwrap();
+        assert_eq!(config.capacity, 3);
+        assert_eq!(config.timeout, Duration::from_secs(6));
+        assert_eq!(config.max_pins, 7);
+        assert_eq!(config.max_upload_size, DEFAULT_MAX_UPLOAD_SIZE);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{
+    channels::{ReadError, ReviseError},
+    clipboard::AddError,
+    password::PasswordError,
+};
+use axum::{
+    extract::{
+        multipart::{MultipartError, MultipartRejection},
+        rejection::{BytesRejection, JsonRejection, PathRejection, QueryRejection},
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use pastebin_core::ErrorBody;
+use std::io;
+
+/// An error response, rendered as an [`ErrorBody`].
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::UNAUTHORIZED, message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        ApiError::new(StatusCode::FORBIDDEN, message)
+    }
+
+    pub fn not_found() -> Self {
+        ApiError::new(StatusCode::NOT_FOUND, "entry not found")
+    }
+
+    pub fn channel_not_found() -> Self {
+        ApiError::new(StatusCode::NOT_FOUND, "channel not found")
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl From<io::Error> for ApiError {
+    fn from(err: io::Error) -> Self {
+        tracing::error!("storage failure: {}", err);
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "storage failure")
+    }
+}
+
+impl From<ReadError> for ApiError {
+    fn from(err: ReadError) -> Self {
+        match err {
+            ReadError::Io(err) => err.into(),
+            ReadError::PasswordRequired => ApiError::unauthorized(err.to_string()),
+            ReadError::WrongPassword => ApiError::forbidden(err.to_string()),
+            ReadError::Password(err) => err.into(),
+        }
+    }
+}
+
+impl From<AddError> for ApiError {
+    fn from(err: AddError) -> Self {
+        match err {
+            AddError::Io(err) => err.into(),
+            AddError::TooLarge { .. } => {
+                ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, err.to_string())
+            }
+            AddError::Full => ApiError::new(StatusCode::INSUFFICIENT_STORAGE, err.to_string()),
+            AddError::TooManyPins { .. } => ApiError::new(StatusCode::CONFLICT, err.to_string()),
+            AddError::Password(err) => err.into(),
+        }
+    }
+}
+
+impl From<PasswordError> for ApiError {
+    fn from(err: PasswordError) -> Self {
+        match err {
+            PasswordError::Io(err) => err.into(),
+            PasswordError::Busy => ApiError::new(StatusCode::SERVICE_UNAVAILABLE, err.to_string()),
+        }
+    }
+}
+
+impl From<ReviseError> for ApiError {
+    fn from(err: ReviseError) -> Self {
+        match err {
+            ReviseError::Read(err) => err.into(),
+            ReviseError::Add(err) => err.into(),
+            ReviseError::Blob | ReviseError::BurnAfterRead => {
+                ApiError::new(StatusCode::CONFLICT, err.to_string())
+            }
+        }
+    }
+}
+
+impl From<MultipartError> for ApiError {
+    fn from(err: MultipartError) -> Self {
+        ApiError::new(err.status(), err.body_text())
+    }
+}
+
+impl From<MultipartRejection> for ApiError {
+    fn from(err: MultipartRejection) -> Self {
+        ApiError::new(err.status(), err.body_text())
+    }
+}
+
+impl From<BytesRejection> for ApiError {
+    fn from(err: BytesRejection) -> Self {
+        ApiError::new(err.status(), err.body_text())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(err: JsonRejection) -> Self {
+        ApiError::new(err.status(), err.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(err: QueryRejection) -> Self {
+        ApiError::new(err.status(), err.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(err: PathRejection) -> Self {
+        ApiError::new(err.status(), err.body_text())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            error: self.message,
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
@@ -0,0 +1,171 @@
+use maud::{html, Markup, PreEscaped};
+use pastebin_core::Entry;
+use std::sync::OnceLock;
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{Theme, ThemeSet},
+    html::{
+        css_for_theme_with_class_style, styled_line_to_highlighted_html, ClassStyle,
+        ClassedHTMLGenerator, IncludeBackground,
+    },
+    parsing::{SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+/// Larger entries are shown without highlighting, it takes too long.
+const MAX_HIGHLIGHT_SIZE: usize = 256 * 1024;
+
+const THEME_NAME: &str = "InspiredGitHub";
+
+/// Classes of the spans emitted by [`classed`], styled by [`classed_css`].
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme() -> &'static Theme {
+    static THEME: OnceLock<Theme> = OnceLock::new();
+    THEME.get_or_init(|| {
+        ThemeSet::load_defaults()
+            .themes
+            .remove(THEME_NAME)
+            .expect("the theme is one of the defaults")
+    })
+}
+
+/// The syntax of an entry, from its language, content type or file extension, or
+/// detected from its first line, e.g. a shebang.
+pub fn syntax(entry: &Entry) -> &'static SyntaxReference {
+    let syntaxes = syntaxes();
+    let subtype = entry.content_type.parse::<mime::Mime>().ok().map(|mime| {
+        let subtype = mime.subtype().as_str().to_owned();
+        subtype.strip_prefix("x-").unwrap_or(&subtype).to_owned()
+    });
+
+    entry
+        .language
+        .as_deref()
+        .and_then(|language| syntaxes.find_syntax_by_token(language))
+        .or_else(|| {
+            let subtype = subtype.filter(|subtype| subtype != "plain")?;
+            syntaxes.find_syntax_by_token(&subtype)
+        })
+        .or_else(|| {
+            let (_, extension) = entry.filename.as_deref()?.rsplit_once('.')?;
+            syntaxes.find_syntax_by_extension(extension)
+        })
+        .or_else(|| syntaxes.find_syntax_by_first_line(&entry.data))
+        .unwrap_or_else(|| syntaxes.find_syntax_plain_text())
+}
+
+/// Renders the data of an entry as a table with one row per line, anchored as `#L<n>`.
+pub fn render(entry: &Entry) -> Markup {
+    let lines = if entry.data.len() > MAX_HIGHLIGHT_SIZE {
+        LinesWithEndings::from(&entry.data).map(escape).collect()
+    } else {
+        highlight(&entry.data, syntax(entry))
+    };
+
+    html! {
+        table.code {
+            @for (index, line) in lines.iter().enumerate() {
+                tr id={ "L" (index + 1) } {
+                    td.ln { a href={ "#L" (index + 1) } { (index + 1) } }
+                    td { (PreEscaped(line)) }
+                }
+            }
+        }
+    }
+}
+
+/// Highlights every line on its own, so each can be put into a row of its own.
+fn highlight(data: &str, syntax: &SyntaxReference) -> Vec<String> {
+    let mut highlighter = HighlightLines::new(syntax, theme());
+    LinesWithEndings::from(data)
+        .map(|line| {
+            let regions = match highlighter.highlight_line(line, syntaxes()) {
+                Ok(regions) => regions,
+                Err(_) => return escape(line),
+            };
+            let regions: Vec<_> = regions
+                .into_iter()
+                .map(|(style, text)| (style, text.trim_end_matches(['\r', '\n'])))
+                .collect();
+            styled_line_to_highlighted_html(&regions, IncludeBackground::No)
+                .unwrap_or_else(|_| escape(line))
+        })
+        .collect()
+}
+
+fn escape(line: &str) -> String {
+    html! { (line.trim_end_matches(['\r', '\n'])) }.into_string()
+}
+
+/// Highlights a code block in `language` with classes instead of inline styles, so
+/// it survives sanitizing that strips `style` attributes.
+pub fn classed(code: &str, language: &str) -> String {
+    let syntaxes = syntaxes();
+    let syntax = syntaxes
+        .find_syntax_by_token(language)
+        .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, syntaxes, CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        if generator
+            .parse_html_for_line_which_includes_newline(line)
+            .is_err()
+        {
+            return html! { pre { code { (code) } } }.into_string();
+        }
+    }
+    format!("<pre><code>{}</code></pre>", generator.finalize())
+}
+
+/// Style sheet for the classes of [`classed`].
+pub fn classed_css() -> &'static str {
+    static CSS: OnceLock<String> = OnceLock::new();
+    CSS.get_or_init(|| css_for_theme_with_class_style(theme(), CLASS_STYLE).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use pastebin_core::Ulid;
+
+    fn entry(data: &str, filename: Option<&str>) -> Entry {
+        Entry {
+            filename: filename.map(str::to_owned),
+            ..test_support::entry(Ulid::new(), data)
+        }
+    }
+
+    #[test]
+    fn filenames_pick_the_language() {
+        let entry = entry("fn main() {}\n", Some("main.rs"));
+        assert_eq!(syntax(&entry).name, "Rust");
+    }
+
+    #[test]
+    fn shebangs_pick_the_language() {
+        let entry = entry("#!/usr/bin/env python3\nprint('hi')\n", None);
+        assert_eq!(syntax(&entry).name, "Python");
+    }
+
+    #[test]
+    fn languages_take_precedence_over_filenames() {
+        let entry = Entry {
+            language: Some("python".to_owned()),
+            ..entry("print('hi')\n", Some("script.rs"))
+        };
+        assert_eq!(syntax(&entry).name, "Python");
+    }
+
+    #[test]
+    fn unknown_contents_are_plain_text() {
+        let entry = entry("just some words\n", Some("notes.unknown"));
+        assert_eq!(syntax(&entry).name, "Plain Text");
+        assert_eq!(classed("x", "no-such-language"), classed("x", "txt"));
+    }
+}
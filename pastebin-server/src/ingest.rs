@@ -0,0 +1,175 @@
+use crate::{
+    channels::{Channels, DEFAULT_CHANNEL},
+    clipboard::AddError,
+    upload::OCTET_STREAM,
+};
+use pastebin_core::{Entry, NewEntry};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time,
+};
+
+/// Settings of the raw TCP listener.
+#[derive(Debug, Clone)]
+pub struct Ingest {
+    /// Reading stops after this long without data, most netcats never close their end.
+    pub idle_timeout: Duration,
+    /// Connections are closed after this long in total, however slowly data trickles in.
+    pub deadline: Duration,
+    pub max_size: usize,
+    pub max_connections: usize,
+    /// Base URL of the HTTP API for the URLs written back, the address the client
+    /// connected to with `http_port` if unset.
+    pub public_url: Option<String>,
+    pub http_port: u16,
+}
+
+/// Number of open connections per client address.
+type Connections = Arc<Mutex<HashMap<IpAddr, usize>>>;
+
+/// Accepts termbin style pastes: everything sent to `listener` until EOF or an idle
+/// timeout becomes an entry of the default channel, and its `/raw` URL is written back.
+pub async fn serve(listener: TcpListener, channels: Arc<Channels>, ingest: Ingest) {
+    let ingest = Arc::new(ingest);
+    let connections = Connections::default();
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                tracing::error!("failed to accept a connection: {}", err);
+                continue;
+            }
+        };
+        let Some(guard) = ConnectionGuard::acquire(&connections, peer.ip(), &ingest) else {
+            tracing::debug!("rejecting connection from {}, too many open", peer);
+            tokio::spawn(reject(stream));
+            continue;
+        };
+
+        let channels = channels.clone();
+        let ingest = ingest.clone();
+        tokio::spawn(async move {
+            let handled = time::timeout(ingest.deadline, handle(stream, peer, &channels, &ingest));
+            match handled.await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => tracing::debug!("paste from {} failed: {}", peer, err),
+                Err(_) => tracing::debug!("paste from {} timed out", peer),
+            }
+            drop(guard);
+        });
+    }
+}
+
+async fn reject(mut stream: TcpStream) {
+    let _ = stream.write_all(b"error: too many connections\n").await;
+}
+
+async fn handle(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    channels: &Channels,
+    ingest: &Ingest,
+) -> io::Result<()> {
+    let mut data = vec![];
+    let mut buf = [0; 8192];
+    loop {
+        match time::timeout(ingest.idle_timeout, stream.read(&mut buf)).await {
+            Ok(Ok(0)) => break,
+            Ok(Ok(read)) => data.extend_from_slice(&buf[..read]),
+            Ok(Err(err)) => return Err(err),
+            Err(_) if data.is_empty() => return Ok(()),
+            Err(_) => break,
+        }
+        if data.len() > ingest.max_size {
+            let message = format!("error: pastes are limited to {} bytes\n", ingest.max_size);
+            return stream.write_all(message.as_bytes()).await;
+        }
+    }
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    let local = stream.local_addr()?;
+    let reply = match store(channels, peer, data).await {
+        Ok(entry) => {
+            tracing::debug!("added clipboard entry {} from {}", entry.id, peer);
+            let base = match &ingest.public_url {
+                Some(url) => url.clone(),
+                None => format!("http://{}", SocketAddr::new(local.ip(), ingest.http_port)),
+            };
+            format!("{}/raw/{}\n", base, entry.id)
+        }
+        Err(AddError::Io(err)) => {
+            tracing::error!("storage failure: {}", err);
+            "error: storage failure\n".to_owned()
+        }
+        Err(err) => format!("error: {}\n", err),
+    };
+    stream.write_all(reply.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Stores `data` the same way `POST /` does, with the client address as source.
+async fn store(channels: &Channels, peer: SocketAddr, data: Vec<u8>) -> Result<Entry, AddError> {
+    let entry = NewEntry {
+        source: Some(peer.ip().to_string()),
+        ..NewEntry::default()
+    };
+    match String::from_utf8(data) {
+        Ok(data) => {
+            channels
+                .paste(DEFAULT_CHANNEL, NewEntry { data, ..entry })
+                .await
+        }
+        Err(err) => {
+            let entry = NewEntry {
+                content_type: Some(OCTET_STREAM.to_owned()),
+                ..entry
+            };
+            channels
+                .upload(DEFAULT_CHANNEL, entry, err.into_bytes().into())
+                .await
+        }
+    }
+}
+
+/// Counts a connection against the limit of its address until dropped.
+struct ConnectionGuard {
+    connections: Connections,
+    ip: IpAddr,
+}
+
+impl ConnectionGuard {
+    fn acquire(connections: &Connections, ip: IpAddr, ingest: &Ingest) -> Option<Self> {
+        let mut open = connections.lock().unwrap();
+        let count = open.entry(ip).or_default();
+        if *count >= ingest.max_connections {
+            return None;
+        }
+        *count += 1;
+        Some(ConnectionGuard {
+            connections: connections.clone(),
+            ip,
+        })
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut open = self.connections.lock().unwrap();
+        if let Some(count) = open.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&self.ip);
+            }
+        }
+    }
+}
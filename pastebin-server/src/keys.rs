@@ -0,0 +1,186 @@
+//! At-rest encryption of what the server writes to its data directory.
+
+use crate::config::Config;
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use sha2::{Digest, Sha256};
+use std::{fmt, fs, io, path::PathBuf};
+
+const KEY_ID_LEN: usize = 8;
+const NONCE_LEN: usize = 24;
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeyError {
+    #[error("failed to read {}: {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("storage keys must be 64 hex digits")]
+    Invalid,
+    #[error("no storage key given")]
+    Empty,
+}
+
+/// Keys encrypting the clipboard logs and uploaded files.
+///
+/// The first key encrypts, the others are previous keys that are only used to
+/// decrypt what was written before a rotation.
+pub struct Keyring {
+    keys: Vec<Key>,
+}
+
+struct Key {
+    /// Start of the SHA-256 of the key, stored along with everything it encrypted.
+    id: [u8; KEY_ID_LEN],
+    cipher: XChaCha20Poly1305,
+}
+
+impl Key {
+    fn new(key: [u8; 32]) -> Self {
+        let mut id = [0; KEY_ID_LEN];
+        id.copy_from_slice(&Sha256::digest(key)[..KEY_ID_LEN]);
+        Key {
+            id,
+            cipher: XChaCha20Poly1305::new(&key.into()),
+        }
+    }
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyring")
+            .field("current", &self.current_id())
+            .field("keys", &self.keys.len())
+            .finish()
+    }
+}
+
+/// The storage keys configured by `storage_key` or `storage_key_file`, if any.
+pub fn load(config: &Config) -> Result<Option<Keyring>, KeyError> {
+    if let Some(keys) = &config.storage_key {
+        return Keyring::parse(keys).map(Some);
+    }
+    let Some(path) = &config.storage_key_file else {
+        return Ok(None);
+    };
+    let keys = fs::read_to_string(path).map_err(|source| KeyError::Read {
+        path: path.to_owned(),
+        source,
+    })?;
+    Keyring::parse(&keys).map(Some)
+}
+
+impl Keyring {
+    /// Parses keys of 64 hex digits separated by commas or whitespace, the current
+    /// key first. `#` starts a comment.
+    pub fn parse(text: &str) -> Result<Self, KeyError> {
+        let mut keys = vec![];
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            for hex_key in line.split([',', ' ', '\t']).filter(|key| !key.is_empty()) {
+                let mut key = [0; 32];
+                hex::decode_to_slice(hex_key, &mut key).map_err(|_| KeyError::Invalid)?;
+                keys.push(Key::new(key));
+            }
+        }
+        if keys.is_empty() {
+            return Err(KeyError::Empty);
+        }
+        Ok(Keyring { keys })
+    }
+
+    /// Id of the key new data is encrypted with, safe to log.
+    pub fn current_id(&self) -> String {
+        hex::encode(self.keys[0].id)
+    }
+
+    /// Encrypts `plaintext` with the current key, prefixed with the id of the key
+    /// and the nonce.
+    pub fn seal(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let key = &self.keys[0];
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = key
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| io::Error::other("encryption failed"))?;
+
+        let mut sealed = Vec::with_capacity(KEY_ID_LEN + NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&key.id);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypts what [`seal`](Self::seal) returned with any of the keys.
+    pub fn open(&self, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        if sealed.len() < KEY_ID_LEN + NONCE_LEN {
+            return Err(invalid_data("encrypted data is truncated".to_owned()));
+        }
+        let (id, rest) = sealed.split_at(KEY_ID_LEN);
+        let Some(key) = self.keys.iter().find(|key| key.id == id) else {
+            return Err(invalid_data(format!(
+                "data was encrypted with the unknown storage key {}",
+                hex::encode(id)
+            )));
+        };
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        key.cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| invalid_data("failed to decrypt, the data is corrupt".to_owned()))
+    }
+
+    /// Whether `sealed` was encrypted with the current key.
+    pub fn is_current(&self, sealed: &[u8]) -> bool {
+        sealed.starts_with(&self.keys[0].id)
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = "1111111111111111111111111111111111111111111111111111111111111111";
+    const NEW: &str = "2222222222222222222222222222222222222222222222222222222222222222";
+
+    #[test]
+    fn sealed_data_opens_after_a_rotation() {
+        let old = Keyring::parse(OLD).unwrap();
+        let sealed = old.seal(b"hello").unwrap();
+        assert!(old.is_current(&sealed));
+        assert_eq!(old.open(&sealed).unwrap(), b"hello");
+
+        let rotated = Keyring::parse(&format!("{NEW} # current\n{OLD}")).unwrap();
+        assert!(!rotated.is_current(&sealed));
+        assert_eq!(rotated.open(&sealed).unwrap(), b"hello");
+        let resealed = rotated.seal(b"hello").unwrap();
+        assert!(rotated.is_current(&resealed));
+        assert_eq!(rotated.open(&resealed).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn data_of_unknown_keys_or_tampered_data_does_not_open() {
+        let old = Keyring::parse(OLD).unwrap();
+        let new = Keyring::parse(NEW).unwrap();
+        let sealed = old.seal(b"hello").unwrap();
+        let err = new.open(&sealed).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains(&old.current_id()));
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(old.open(&tampered).is_err());
+        assert!(old.open(&sealed[..KEY_ID_LEN + NONCE_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn keys_are_parsed_from_lists_with_comments() {
+        let keys = Keyring::parse(&format!("# keys\n{NEW}, {OLD}\n")).unwrap();
+        assert_eq!(keys.keys.len(), 2);
+        assert!(matches!(Keyring::parse("# none"), Err(KeyError::Empty)));
+        assert!(matches!(Keyring::parse("abcd"), Err(KeyError::Invalid)));
+    }
+}
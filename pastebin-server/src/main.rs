@@ -1,111 +1,202 @@
+use auth::Tokens;
 use axum::{
     error_handling::HandleErrorLayer,
-    extract::State,
+    extract::DefaultBodyLimit,
     http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
-    Json, Router,
-};
-use serde::{Deserialize, Serialize};
-use std::{
-    net::SocketAddr,
-    sync::{Arc, RwLock},
-    time::Duration,
+    middleware,
+    routing::{get, post, put},
+    Extension, Router,
 };
+use channels::Channels;
+use config::{Command, Config};
+use error::ApiError;
+use ingest::Ingest;
+use plain::PublicUrl;
+use std::{process, sync::Arc, time::Duration};
 use tower::{BoxError, ServiceBuilder};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-const CLIPBOARD_SIZE: usize = 10;
-
-type SharedClipboard = Arc<RwLock<Clipboard>>;
+mod api;
+mod auth;
+mod channels;
+mod clipboard;
+mod config;
+mod error;
+mod extract;
+mod highlight;
+mod ingest;
+mod keys;
+mod markdown;
+mod password;
+mod plain;
+mod search;
+mod storage;
+mod subscribe;
+#[cfg(test)]
+mod test_support;
+mod upload;
+mod web;
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct Entry {
-    data: String,
-}
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
 
-#[derive(Debug, Clone)]
-struct Clipboard {
-    queue: Vec<Entry>,
-    capacity: usize,
-}
-
-impl Clipboard {
-    fn add(&mut self, entry: Entry) {
-        if self.queue.len() == self.capacity {
-            self.queue.remove(0);
-        }
-        self.queue.push(entry);
-    }
-
-    fn get_entries(&self) -> Vec<Entry> {
-        self.queue.clone()
-    }
-}
+#[tokio::main]
+async fn main() {
+    let (config, command) = Config::load().unwrap_or_else(|err| {
+        eprintln!("pastebin-server: invalid configuration: {}", err);
+        process::exit(2);
+    });
 
-impl Default for Clipboard {
-    fn default() -> Self {
-        Clipboard {
-            queue: vec![],
-            capacity: CLIPBOARD_SIZE,
+    if let Some(Command::Token(command)) = command {
+        let Some(path) = &config.tokens_file else {
+            eprintln!("pastebin-server: no tokens file, set --tokens-file or --data-dir");
+            process::exit(2);
+        };
+        if let Err(err) = auth::run(command, path) {
+            eprintln!("pastebin-server: {}", err);
+            process::exit(1);
         }
+        return;
     }
-}
 
-#[tokio::main]
-async fn main() {
     tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "pastebin_server=debug,tower_http=debug".into()),
-        )
+        .with(tracing_subscriber::EnvFilter::new(&config.log))
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let clipboard = SharedClipboard::default();
+    let keys = keys::load(&config).unwrap_or_else(|err| {
+        eprintln!("pastebin-server: invalid storage key: {}", err);
+        process::exit(2);
+    });
+    let keys = keys.map(Arc::new);
+    if let Some(dir) = &config.data_dir {
+        tracing::debug!("storing clipboards in {}", dir.display());
+        if let Some(keys) = &keys {
+            tracing::debug!("encrypting them with storage key {}", keys.current_id());
+        }
+    }
+    let channels = Channels::open(&config, keys.clone()).unwrap_or_else(|err| {
+        eprintln!("pastebin-server: failed to open the clipboards: {}", err);
+        process::exit(1);
+    });
+    let channels = Arc::new(channels);
+    tokio::spawn(sweep_expired(channels.clone()));
+    if keys.is_some() && config.data_dir.is_some() {
+        tokio::task::spawn_blocking({
+            let channels = channels.clone();
+            move || reseal(&channels)
+        });
+    }
+    if let Some(addr) = config.ingest_listen {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .unwrap_or_else(|err| {
+                eprintln!("pastebin-server: failed to listen on {}: {}", addr, err);
+                process::exit(1);
+            });
+        let ingest = Ingest {
+            idle_timeout: config.ingest_timeout,
+            deadline: config.timeout,
+            max_size: config.max_upload_size,
+            max_connections: config.ingest_max_connections,
+            public_url: config.public_url.clone(),
+            http_port: config.listen.port(),
+        };
+        tracing::debug!("accepting raw pastes on {}", addr);
+        tokio::spawn(ingest::serve(listener, channels.clone(), ingest));
+    }
+    let tokens =
+        Tokens::open(config.tokens_file.clone(), config.require_tokens).unwrap_or_else(|err| {
+            eprintln!("pastebin-server: failed to load the API tokens: {}", err);
+            process::exit(1);
+        });
+    let tokens = Arc::new(tokens);
 
     let app = Router::new()
-        .route("/paste", post(add_entry))
-        .route("/copy", get(get_entries))
+        .route("/", get(web::index).post(plain::paste))
+        .route("/view/:id", get(web::view).post(web::reveal))
+        .route("/web/paste", post(web::paste))
+        .route("/paste", post(api::add_entry))
+        .route("/copy", get(api::get_entries))
+        .route(
+            "/entries/:id",
+            get(api::get_entry)
+                .put(api::revise_entry)
+                .delete(api::delete_entry),
+        )
+        .route("/entries/:id/revisions", get(api::get_revisions))
+        .route("/entries/:id/diff", get(api::diff_revisions))
+        .route(
+            "/entries/:id/pin",
+            put(api::pin_entry).delete(api::unpin_entry),
+        )
+        .route("/entries/:id/content", get(upload::get_content))
+        .route("/entries/:id/rendered", get(web::rendered))
+        .route("/raw/:id", get(plain::raw))
+        .route("/upload", post(upload::upload))
+        .route("/c/:name", post(plain::paste_channel))
+        .route("/c/:name/paste", post(api::add_channel_entry))
+        .route("/c/:name/copy", get(api::get_channel_entries))
+        .route("/c/:name/upload", post(upload::upload_channel))
+        .route("/channels", get(api::list_channels))
+        .route("/search", get(api::search))
+        .route("/subscribe", get(subscribe::sse))
+        .route("/ws", get(subscribe::ws))
+        .route("/admin/channels/:name", put(api::configure_channel))
         .layer(
             ServiceBuilder::new()
                 .layer(HandleErrorLayer::new(|error: BoxError| async move {
                     if error.is::<tower::timeout::error::Elapsed>() {
-                        Ok(StatusCode::REQUEST_TIMEOUT)
+                        ApiError::new(StatusCode::REQUEST_TIMEOUT, "request timed out")
                     } else {
-                        Err((
+                        ApiError::new(
                             StatusCode::INTERNAL_SERVER_ERROR,
                             format!("Unhandled internal error: {}", error),
-                        ))
+                        )
                     }
                 }))
-                .timeout(Duration::from_secs(10))
+                .timeout(config.timeout)
                 .layer(TraceLayer::new_for_http())
+                .layer(middleware::from_fn_with_state(tokens, auth::require_token))
+                .layer(DefaultBodyLimit::max(config.max_upload_size))
+                .layer(Extension(PublicUrl(config.public_url.clone())))
                 .into_inner(),
         )
-        .with_state(clipboard);
+        .with_state(channels);
 
-    let addr = SocketAddr::from(([192, 168, 0, 10], 3000));
-    tracing::debug!("listening on {}", addr);
+    let server = axum::Server::try_bind(&config.listen).unwrap_or_else(|err| {
+        eprintln!(
+            "pastebin-server: failed to listen on {}: {}",
+            config.listen, err
+        );
+        process::exit(1);
+    });
+    tracing::debug!("listening on {}", config.listen);
 
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    server.serve(app.into_make_service()).await.unwrap();
 }
 
-async fn add_entry(
-    State(clipboard): State<SharedClipboard>,
-    Json(entry): Json<Entry>,
-) -> impl IntoResponse {
-    clipboard.write().unwrap().add(entry);
-    tracing::debug!("added clipboard entry");
-    StatusCode::OK
+/// Re-encrypts the logs and uploaded files after the storage key was set or rotated.
+fn reseal(channels: &Channels) {
+    match channels.reseal_logs() {
+        Ok(resealed) => tracing::debug!("re-encrypted {} clipboard logs", resealed),
+        Err(err) => tracing::error!("failed to re-encrypt clipboard logs: {}", err),
+    }
+    match channels.reseal_blobs() {
+        Ok(resealed) => tracing::debug!("re-encrypted {} uploaded files", resealed),
+        Err(err) => tracing::error!("failed to re-encrypt uploaded files: {}", err),
+    }
 }
 
-async fn get_entries(State(clipboard): State<SharedClipboard>) -> impl IntoResponse {
-    tracing::debug!("fetching clipboard");
-    let entries = clipboard.read().unwrap().get_entries();
-    (StatusCode::OK, Json(entries))
+/// Purges expired entries in the background, reads already skip them before that.
+async fn sweep_expired(channels: Arc<Channels>) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        match channels.purge_expired() {
+            Ok(0) => {}
+            Ok(purged) => tracing::debug!("purged {} expired entries", purged),
+            Err(err) => tracing::error!("failed to purge expired entries: {}", err),
+        }
+    }
 }
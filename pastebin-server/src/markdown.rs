@@ -0,0 +1,110 @@
+use crate::highlight;
+use ammonia::Builder;
+use pulldown_cmark::{html, CodeBlockKind, Event, Options, Parser, Tag};
+use std::sync::OnceLock;
+
+/// Renders `markdown` to HTML with highlighted code blocks.
+///
+/// The result is sanitized, pastes are untrusted and Markdown allows raw HTML.
+pub fn render(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+
+    let mut events = vec![];
+    // language and text of the code block being read
+    let mut code: Option<(String, String)> = None;
+    for event in Parser::new_ext(markdown, options) {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => info
+                        .split_whitespace()
+                        .next()
+                        .unwrap_or_default()
+                        .to_owned(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code = Some((language, String::new()));
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                if let Some((language, text)) = code.take() {
+                    events.push(Event::Html(highlight::classed(&text, &language).into()));
+                }
+            }
+            Event::Text(text) if code.is_some() => {
+                if let Some((_, code)) = &mut code {
+                    code.push_str(&text);
+                }
+            }
+            event => events.push(event),
+        }
+    }
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, events.into_iter());
+    sanitizer().clean(&unsafe_html).to_string()
+}
+
+fn sanitizer() -> &'static Builder<'static> {
+    static SANITIZER: OnceLock<Builder<'static>> = OnceLock::new();
+    SANITIZER.get_or_init(|| {
+        let mut builder = Builder::default();
+        builder
+            .add_tags(["input"])
+            .add_tag_attributes("input", ["type", "checked", "disabled"])
+            .add_tag_attributes("span", ["class"]);
+        builder
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts_are_stripped() {
+        let html = render("hello <script>alert(1)</script>");
+        assert!(!html.contains("<script"), "{html}");
+        assert!(!html.contains("alert"), "{html}");
+    }
+
+    #[test]
+    fn event_handlers_are_stripped() {
+        let html =
+            render(r#"<img src="x.png" onerror="alert(1)"> <a href="/" onclick="alert(2)">x</a>"#);
+        assert!(html.contains("<img"), "{html}");
+        assert!(!html.contains("onerror"), "{html}");
+        assert!(!html.contains("onclick"), "{html}");
+    }
+
+    #[test]
+    fn javascript_links_are_stripped() {
+        for markdown in [
+            "[click](javascript:alert(1))",
+            r#"<a href="javascript:alert(1)">click</a>"#,
+        ] {
+            let html = render(markdown);
+            assert!(html.contains("click"), "{html}");
+            assert!(!html.contains("javascript:"), "{html}");
+        }
+    }
+
+    #[test]
+    fn iframes_and_styles_are_stripped() {
+        let html = render(
+            "<iframe src=\"https://example.com\"></iframe>\n\n<style>body { display: none }</style>",
+        );
+        assert!(!html.contains("<iframe"), "{html}");
+        assert!(!html.contains("<style"), "{html}");
+        assert!(!html.contains("display"), "{html}");
+    }
+
+    #[test]
+    fn task_lists_and_highlighting_survive() {
+        let html = render("- [x] done\n\n```rust\nfn main() {}\n```");
+        assert!(html.contains(r#"type="checkbox""#), "{html}");
+        assert!(html.contains("<span class="), "{html}");
+    }
+}
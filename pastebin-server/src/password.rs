@@ -0,0 +1,86 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use std::{io, sync::OnceLock};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Passwords hashed or verified at once, Argon2 takes 19 MiB of memory every time.
+const MAX_CONCURRENT: usize = 8;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PasswordError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("too many passwords are being checked, try again later")]
+    Busy,
+}
+
+/// Hashes the password of a protected entry for storage.
+///
+/// Hashing takes tens of milliseconds of CPU on purpose, so it runs on a blocking
+/// thread rather than holding up the runtime.
+pub async fn hash(password: String) -> Result<String, PasswordError> {
+    let permit = permit()?;
+    let hash = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        hash_now(&password)
+    });
+    Ok(hash.await.map_err(io::Error::other)??)
+}
+
+fn hash_now(password: &str) -> io::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| io::Error::other(err.to_string()))
+}
+
+/// Checks `password` against a hash from [`hash`], a corrupt hash matches nothing.
+///
+/// Runs on a blocking thread like [`hash`].
+pub async fn verify(password: String, hash: String) -> Result<bool, PasswordError> {
+    let permit = permit()?;
+    let verified = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        verify_now(&password, &hash)
+    });
+    Ok(verified.await.unwrap_or(false))
+}
+
+/// A slot to hash or verify a password in, held until the blocking thread is done
+/// even if the request is dropped before.
+fn permit() -> Result<SemaphorePermit<'static>, PasswordError> {
+    static PERMITS: OnceLock<Semaphore> = OnceLock::new();
+    PERMITS
+        .get_or_init(|| Semaphore::new(MAX_CONCURRENT))
+        .try_acquire()
+        .map_err(|_| PasswordError::Busy)
+}
+
+fn verify_now(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn passwords_are_turned_away_while_every_slot_is_taken() {
+        let hash = hash("hunter2".to_owned()).await.unwrap();
+        assert!(verify("hunter2".to_owned(), hash.clone()).await.unwrap());
+        assert!(!verify("hunter3".to_owned(), hash.clone()).await.unwrap());
+
+        let permits: Vec<_> = (0..MAX_CONCURRENT).map(|_| permit().unwrap()).collect();
+        let busy = verify("hunter2".to_owned(), hash.clone()).await;
+        assert!(matches!(busy, Err(PasswordError::Busy)));
+        drop(permits);
+        assert!(verify("hunter2".to_owned(), hash).await.unwrap());
+    }
+}
@@ -0,0 +1,119 @@
+use crate::{
+    api::{password, validate, validate_name},
+    channels::{Channels, DEFAULT_CHANNEL},
+    error::ApiError,
+    extract::{Path, Query},
+    upload::{UploadQuery, OCTET_STREAM},
+};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
+use pastebin_core::{Ulid, DEFAULT_CONTENT_TYPE};
+use std::sync::Arc;
+
+/// Base URL the server is reachable at, used in the URLs handed out to plain text
+/// clients. Taken from the `Host` header of the request if not configured.
+#[derive(Debug, Clone)]
+pub struct PublicUrl(pub Option<String>);
+
+pub async fn paste(
+    State(channels): State<Arc<Channels>>,
+    Extension(public_url): Extension<PublicUrl>,
+    Query(query): Query<UploadQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, ApiError> {
+    store(
+        &channels,
+        DEFAULT_CHANNEL,
+        &public_url,
+        query,
+        &headers,
+        body,
+    )
+    .await
+}
+
+pub async fn paste_channel(
+    State(channels): State<Arc<Channels>>,
+    Extension(public_url): Extension<PublicUrl>,
+    Path(name): Path<String>,
+    Query(query): Query<UploadQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, ApiError> {
+    validate_name(&name)?;
+    store(&channels, &name, &public_url, query, &headers, body).await
+}
+
+/// Stores the raw request body and replies with the URL of its `/raw` page, so
+/// `cmd | curl --data-binary @- host/` works without building JSON.
+///
+/// The content type of the request is ignored, curl sends form data by default.
+/// Bodies that are not UTF-8 are stored like uploads.
+async fn store(
+    channels: &Channels,
+    name: &str,
+    public_url: &PublicUrl,
+    mut query: UploadQuery,
+    headers: &HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, ApiError> {
+    query.password = password(headers).map(str::to_owned);
+    let entry = match std::str::from_utf8(&body) {
+        Ok(data) => {
+            let mut entry = query.into_entry(DEFAULT_CONTENT_TYPE.to_owned());
+            entry.data = data.to_owned();
+            validate(&entry, channels.max_ttl())?;
+            channels.paste(name, entry).await?
+        }
+        Err(_) => {
+            let entry = query.into_entry(OCTET_STREAM.to_owned());
+            validate(&entry, channels.max_ttl())?;
+            channels.upload(name, entry, body).await?
+        }
+    };
+    tracing::debug!("added clipboard entry {}", entry.id);
+
+    let url = format!("{}/raw/{}", base_url(public_url, headers), entry.id);
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, url.clone())],
+        format!("{}\n", url),
+    ))
+}
+
+fn base_url(public_url: &PublicUrl, headers: &HeaderMap) -> String {
+    if let Some(url) = &public_url.0 {
+        return url.clone();
+    }
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or("localhost");
+    format!("http://{}", host)
+}
+
+/// Serves the contents of an entry as plain text.
+pub async fn raw(
+    State(channels): State<Arc<Channels>>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    tracing::debug!("fetching raw clipboard entry {}", id);
+    let (_, contents) = channels
+        .read_content(id, password(&headers))
+        .await?
+        .ok_or_else(ApiError::not_found)?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        contents,
+    ))
+}
@@ -0,0 +1,303 @@
+//! Full-text search over the entries of a clipboard.
+
+use pastebin_core::{Entry, Ulid};
+use std::collections::HashMap;
+
+/// Only the start of larger entries is indexed.
+const MAX_INDEXED_SIZE: usize = 1024 * 1024;
+
+/// Metadata is indexed at positions this far behind the data, so phrases never span
+/// both.
+const METADATA_GAP: u32 = 1 << 30;
+
+/// Bytes of context shown before the first match of a snippet.
+const SNIPPET_CONTEXT: usize = 60;
+
+const SNIPPET_LEN: usize = 240;
+
+/// Inverted index of the words in the data and metadata of entries.
+///
+/// Protected, encrypted and burn-after-read entries are never indexed, uploaded files
+/// only by their metadata.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    /// Positions of every word in every entry it occurs in.
+    postings: HashMap<String, HashMap<Ulid, Vec<u32>>>,
+    /// The distinct words of every entry, to remove it again.
+    words: HashMap<Ulid, Vec<String>>,
+}
+
+impl SearchIndex {
+    pub fn insert(&mut self, entry: &Entry) {
+        if entry.protected || entry.burn_after_read {
+            return;
+        }
+        let mut positions: HashMap<String, Vec<u32>> = HashMap::new();
+        if !entry.blob && !entry.encrypted {
+            let data = truncate(&entry.data, MAX_INDEXED_SIZE);
+            for (position, (_, word)) in words(data).enumerate() {
+                positions
+                    .entry(word.to_lowercase())
+                    .or_default()
+                    .push(position as u32);
+            }
+        }
+        let metadata = [
+            entry.filename.as_deref(),
+            entry.source.as_deref(),
+            entry.language.as_deref(),
+            Some(entry.content_type.as_str()),
+        ];
+        let metadata = metadata.into_iter().flatten().flat_map(words);
+        for (position, (_, word)) in metadata.enumerate() {
+            positions
+                .entry(word.to_lowercase())
+                .or_default()
+                .push(METADATA_GAP + position as u32);
+        }
+
+        let words = positions.keys().cloned().collect();
+        for (word, positions) in positions {
+            self.postings
+                .entry(word)
+                .or_default()
+                .insert(entry.id, positions);
+        }
+        self.words.insert(entry.id, words);
+    }
+
+    pub fn remove(&mut self, id: Ulid) {
+        for word in self.words.remove(&id).unwrap_or_default() {
+            if let Some(postings) = self.postings.get_mut(&word) {
+                postings.remove(&id);
+                if postings.is_empty() {
+                    self.postings.remove(&word);
+                }
+            }
+        }
+    }
+
+    /// Ids of the entries matching every term of `query`, with how often they match.
+    pub fn search(&self, query: &Query) -> Vec<(Ulid, usize)> {
+        let mut terms = query.terms.iter();
+        let Some(first) = terms.next() else {
+            return vec![];
+        };
+        let mut hits = self.matches(first);
+        for term in terms {
+            let matches = self.matches(term);
+            hits.retain(|id, count| match matches.get(id) {
+                Some(more) => {
+                    *count += more;
+                    true
+                }
+                None => false,
+            });
+        }
+        hits.into_iter().collect()
+    }
+
+    /// Entries containing `phrase`, with the number of times they do.
+    fn matches(&self, phrase: &[String]) -> HashMap<Ulid, usize> {
+        let Some((first, rest)) = phrase.split_first() else {
+            return HashMap::new();
+        };
+        let Some(starts) = self.postings.get(first) else {
+            return HashMap::new();
+        };
+        starts
+            .iter()
+            .filter_map(|(id, starts)| {
+                let count = starts
+                    .iter()
+                    .filter(|&&start| {
+                        rest.iter().zip(1..).all(|(word, offset)| {
+                            self.postings
+                                .get(word)
+                                .and_then(|postings| postings.get(id))
+                                .is_some_and(|positions| {
+                                    positions.binary_search(&(start + offset)).is_ok()
+                                })
+                        })
+                    })
+                    .count();
+                (count > 0).then_some((*id, count))
+            })
+            .collect()
+    }
+}
+
+/// A parsed search query, words and `"quoted phrases"` that all have to match.
+#[derive(Debug)]
+pub struct Query {
+    /// Lowercase words of every term, a phrase has more than one.
+    terms: Vec<Vec<String>>,
+}
+
+impl Query {
+    /// Parses `query`, returns `None` if it holds no words.
+    pub fn parse(query: &str) -> Option<Self> {
+        let mut terms = vec![];
+        for (index, part) in query.split('"').enumerate() {
+            let part_words = words(part).map(|(_, word)| word.to_lowercase());
+            if index % 2 == 1 {
+                let phrase: Vec<_> = part_words.collect();
+                if !phrase.is_empty() {
+                    terms.push(phrase);
+                }
+            } else {
+                terms.extend(part_words.map(|word| vec![word]));
+            }
+        }
+        (!terms.is_empty()).then_some(Query { terms })
+    }
+}
+
+/// An excerpt of `data` around the first match of `query`, with the byte ranges of
+/// the matches within it.
+pub fn snippet(data: &str, query: &Query) -> (String, Vec<(usize, usize)>) {
+    let data = truncate(data, MAX_INDEXED_SIZE);
+    let words: Vec<_> = words(data)
+        .map(|(start, word)| (start, start + word.len(), word.to_lowercase()))
+        .collect();
+    let mut matches = vec![];
+    for term in &query.terms {
+        for window in words.windows(term.len()) {
+            if window
+                .iter()
+                .zip(term)
+                .all(|((_, _, word), term)| word == term)
+            {
+                matches.push((window[0].0, window[term.len() - 1].1));
+            }
+        }
+    }
+    matches.sort_unstable();
+
+    let first = matches.first().map_or(0, |&(start, _)| start);
+    let start = floor_char_boundary(data, first.saturating_sub(SNIPPET_CONTEXT));
+    let end = floor_char_boundary(data, (start + SNIPPET_LEN).min(data.len()));
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let offset = snippet.len();
+    snippet.push_str(&data[start..end]);
+    if end < data.len() {
+        snippet.push('…');
+    }
+
+    let highlights = matches
+        .into_iter()
+        .filter(|&(from, to)| from >= start && to <= end)
+        .map(|(from, to)| (from - start + offset, to - start + offset))
+        .collect();
+    (snippet, highlights)
+}
+
+/// The words of `text` with their byte offsets, runs of alphanumeric characters.
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(move |word| (word.as_ptr() as usize - text.as_ptr() as usize, word))
+}
+
+fn truncate(text: &str, len: usize) -> &str {
+    &text[..floor_char_boundary(text, len.min(text.len()))]
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn entry(id: u64, data: &str) -> Entry {
+        test_support::entry(Ulid::from_parts(id, 0), data)
+    }
+
+    fn search(index: &SearchIndex, query: &str) -> Vec<(u64, usize)> {
+        let query = Query::parse(query).unwrap();
+        let mut hits: Vec<_> = index
+            .search(&query)
+            .into_iter()
+            .map(|(id, count)| (id.timestamp_ms(), count))
+            .collect();
+        hits.sort_unstable();
+        hits
+    }
+
+    #[test]
+    fn phrases_match_consecutive_words() {
+        let mut index = SearchIndex::default();
+        index.insert(&entry(1, "The quick brown fox, the QUICK brown dog"));
+        index.insert(&entry(2, "brown quick"));
+        index.insert(&Entry {
+            filename: Some("brown.txt".to_owned()),
+            ..entry(3, "quick")
+        });
+
+        assert_eq!(search(&index, "\"quick brown\""), [(1, 2)]);
+        assert_eq!(search(&index, "quick brown"), [(1, 4), (2, 2), (3, 2)]);
+        assert_eq!(search(&index, "\"quick brown\" dog"), [(1, 3)]);
+        // phrases never span the data and the metadata
+        assert_eq!(search(&index, "\"quick brown txt\""), []);
+        assert_eq!(search(&index, "\"brown txt\""), [(3, 1)]);
+        assert!(Query::parse(" \"\" ").is_none());
+
+        index.remove(Ulid::from_parts(1, 0));
+        assert_eq!(search(&index, "\"quick brown\""), []);
+        assert!(!index.postings.contains_key("fox"));
+    }
+
+    #[test]
+    fn hidden_entries_are_not_indexed() {
+        let mut index = SearchIndex::default();
+        index.insert(&Entry {
+            protected: true,
+            ..entry(1, "secret")
+        });
+        index.insert(&Entry {
+            burn_after_read: true,
+            ..entry(2, "secret")
+        });
+        index.insert(&Entry {
+            encrypted: true,
+            ..entry(3, "secret")
+        });
+        assert_eq!(search(&index, "secret"), []);
+        assert_eq!(search(&index, "plain"), [(3, 1)]);
+    }
+
+    #[test]
+    fn snippets_highlight_the_matches() {
+        let query = Query::parse("fox \"lazy dog\"").unwrap();
+        let (snippet, highlights) = snippet("The fox jumps over the LAZY  dog.", &query);
+        assert_eq!(snippet, "The fox jumps over the LAZY  dog.");
+        let highlighted: Vec<_> = highlights
+            .iter()
+            .map(|&(from, to)| &snippet[from..to])
+            .collect();
+        assert_eq!(highlighted, ["fox", "LAZY  dog"]);
+    }
+
+    #[test]
+    fn snippets_start_shortly_before_the_first_match() {
+        let query = Query::parse("fox").unwrap();
+        let data = format!("{} fox {}", "é".repeat(100), "x".repeat(300));
+        let (snippet, highlights) = snippet(&data, &query);
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'));
+        assert!(snippet.len() <= SNIPPET_LEN + 2 * '…'.len_utf8());
+        let &[(from, to)] = highlights.as_slice() else {
+            panic!("expected one match, got {:?}", highlights);
+        };
+        assert_eq!(&snippet[from..to], "fox");
+        assert!(from <= '…'.len_utf8() + SNIPPET_CONTEXT + 1);
+    }
+}
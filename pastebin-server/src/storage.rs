@@ -0,0 +1,721 @@
+use crate::keys::Keyring;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use chrono::Utc;
+use pastebin_core::{Entry, Revision, Ulid, DEFAULT_CONTENT_TYPE};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+/// Number of records a log may hold beyond its live entries before it is compacted.
+const COMPACT_SLACK: usize = 64;
+
+/// Start of blob files encrypted with a storage key.
+const SEALED_BLOB_MAGIC: &[u8] = b"PBSEAL1\0";
+
+/// An entry as stored by the server, along with what is never sent to clients.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StoredEntry {
+    #[serde(flatten, deserialize_with = "logged_entry")]
+    pub entry: Entry,
+    /// Argon2 hash of the password needed to read the entry, in PHC string format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+    /// Earlier revisions of the entry, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub revisions: Vec<Revision>,
+}
+
+impl StoredEntry {
+    /// Size of the entry and its earlier revisions in bytes.
+    pub fn bytes(&self) -> usize {
+        self.entry.size + self.revisions.iter().map(|r| r.size).sum::<usize>()
+    }
+}
+
+/// Reads an entry back from a log, filling in the fields older versions did not log
+/// so the strict [`Entry`] accepts it. [`migrate`] replaces the placeholders.
+fn logged_entry<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Entry, D::Error> {
+    let mut fields = Map::<String, Value>::deserialize(deserializer)?;
+    let defaults = [
+        ("id", json!(Ulid::nil())),
+        ("created_at", json!(Utc::now())),
+        ("size", json!(0)),
+        ("content_type", json!(DEFAULT_CONTENT_TYPE)),
+        ("burn_after_read", json!(false)),
+    ];
+    for (field, default) in defaults {
+        fields.entry(field).or_insert(default);
+    }
+    serde_json::from_value(Value::Object(fields)).map_err(D::Error::custom)
+}
+
+/// Backing store of the entries of a [`Clipboard`](crate::clipboard::Clipboard).
+pub trait Storage: Send + Sync {
+    /// All stored entries, oldest first.
+    fn entries(&self) -> &[StoredEntry];
+
+    /// Appends `entry` as the newest entry.
+    fn push(&mut self, entry: StoredEntry) -> io::Result<()>;
+
+    /// Removes the entry at `index` and returns it.
+    fn remove(&mut self, index: usize) -> io::Result<StoredEntry>;
+
+    /// Replaces the entry at `index` with `entry`.
+    fn replace(&mut self, index: usize, entry: StoredEntry) -> io::Result<()>;
+
+    /// Rewrites what was stored unencrypted or with an older storage key with the
+    /// current key, returns whether there was any.
+    fn reseal(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+/// Keeps entries in memory only, they are lost when the server stops.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    entries: Vec<StoredEntry>,
+}
+
+impl Storage for MemoryStorage {
+    fn entries(&self) -> &[StoredEntry] {
+        &self.entries
+    }
+
+    fn push(&mut self, entry: StoredEntry) -> io::Result<()> {
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn remove(&mut self, index: usize) -> io::Result<StoredEntry> {
+        Ok(self.entries.remove(index))
+    }
+
+    fn replace(&mut self, index: usize, entry: StoredEntry) -> io::Result<()> {
+        self.entries[index] = entry;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Record {
+    Push { entry: StoredEntry },
+    Remove { index: usize },
+    Replace { index: usize, entry: StoredEntry },
+}
+
+/// A line of the log, records are sealed with the storage key if there is one.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Sealed { sealed: String },
+    Plain(Box<Record>),
+}
+
+/// Journals every change to an append-only file of JSON lines and replays it on open.
+///
+/// The entries are mirrored in memory, so reads never touch the disk. With storage
+/// keys every record is encrypted, records written before the current key was set
+/// are re-encrypted by [`Storage::reseal`].
+#[derive(Debug)]
+pub struct LogStorage {
+    entries: Vec<StoredEntry>,
+    path: PathBuf,
+    file: BufWriter<File>,
+    records: usize,
+    keys: Option<Arc<Keyring>>,
+    /// Some records are unencrypted or sealed with an older key.
+    stale: bool,
+}
+
+impl LogStorage {
+    /// Opens the log at `path`, creating it if it does not exist yet.
+    pub fn open(path: impl Into<PathBuf>, keys: Option<Arc<Keyring>>) -> io::Result<Self> {
+        let path = path.into();
+        let Replayed {
+            mut entries,
+            records,
+            stale,
+            torn,
+        } = match File::open(&path) {
+            Ok(file) => replay(&path, file, keys.as_deref())?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Replayed::default(),
+            Err(err) => return Err(err),
+        };
+        let migrated = migrate(&mut entries);
+
+        // re-encrypting is left to `reseal` in the background, so the server can
+        // start serving right away
+        if stale && !torn && !migrated {
+            return Ok(LogStorage {
+                file: BufWriter::new(OpenOptions::new().append(true).open(&path)?),
+                records,
+                entries,
+                path,
+                keys,
+                stale,
+            });
+        }
+        Ok(LogStorage {
+            file: rewrite(&path, &entries, keys.as_deref())?,
+            records: entries.len(),
+            entries,
+            path,
+            keys,
+            stale: false,
+        })
+    }
+
+    /// Rewrites the log so it only holds the live entries, sealed with the current key.
+    fn compact(&mut self) -> io::Result<()> {
+        self.file = rewrite(&self.path, &self.entries, self.keys.as_deref())?;
+        self.records = self.entries.len();
+        self.stale = false;
+        Ok(())
+    }
+
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        write_record(&mut self.file, record, self.keys.as_deref())?;
+        self.file.flush()?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Compacts the log once it grew too far beyond the entries, only after the
+    /// entries reflect the last appended record.
+    fn maybe_compact(&mut self) -> io::Result<()> {
+        if self.records > self.entries.len() * 2 + COMPACT_SLACK {
+            self.compact()?;
+        }
+        Ok(())
+    }
+}
+
+impl Storage for LogStorage {
+    fn entries(&self) -> &[StoredEntry] {
+        &self.entries
+    }
+
+    fn push(&mut self, entry: StoredEntry) -> io::Result<()> {
+        self.append(&Record::Push {
+            entry: entry.clone(),
+        })?;
+        self.entries.push(entry);
+        self.maybe_compact()
+    }
+
+    fn remove(&mut self, index: usize) -> io::Result<StoredEntry> {
+        self.append(&Record::Remove { index })?;
+        let entry = self.entries.remove(index);
+        if entry.entry.burn_after_read || !entry.revisions.is_empty() {
+            // burned entries often hold secrets, and removing an entry removes all of
+            // its revisions, neither may linger in the log
+            self.compact()?;
+        } else {
+            self.maybe_compact()?;
+        }
+        Ok(entry)
+    }
+
+    fn replace(&mut self, index: usize, entry: StoredEntry) -> io::Result<()> {
+        self.append(&Record::Replace {
+            index,
+            entry: entry.clone(),
+        })?;
+        self.entries[index] = entry;
+        self.maybe_compact()
+    }
+
+    fn reseal(&mut self) -> io::Result<bool> {
+        if !self.stale {
+            return Ok(false);
+        }
+        self.compact()?;
+        Ok(true)
+    }
+}
+
+/// Fills in what entries logged by older versions lack, returns whether there was
+/// anything, the log has to be rewritten with the result then.
+fn migrate(entries: &mut [StoredEntry]) -> bool {
+    // entries from before ids were assigned are the oldest, they get ids in the
+    // order they were pasted that sort before the first assigned one
+    let missing = entries.iter().filter(|e| e.entry.id.is_nil()).count();
+    let first = entries.iter().map(|e| e.entry.id).find(|id| !id.is_nil());
+    let first = first.map_or_else(|| Ulid::new().timestamp_ms(), |id| id.timestamp_ms());
+    let start = first.saturating_sub(missing as u64);
+    let nil = entries.iter_mut().filter(|e| e.entry.id.is_nil());
+    for (offset, stored) in (0..).zip(nil) {
+        stored.entry.id = Ulid::from_parts(start + offset, rand::random());
+    }
+    let mut migrated = missing > 0;
+    for stored in entries.iter_mut().filter(|e| !e.entry.blob) {
+        migrated |= stored.entry.size != stored.entry.data.len();
+        stored.entry.size = stored.entry.data.len();
+    }
+    migrated
+}
+
+/// Atomically replaces the log at `path` with one push record per entry and
+/// returns it opened for appending.
+fn rewrite(
+    path: &Path,
+    entries: &[StoredEntry],
+    keys: Option<&Keyring>,
+) -> io::Result<BufWriter<File>> {
+    let tmp = path.with_extension("tmp");
+    let mut file = BufWriter::new(File::create(&tmp)?);
+    for entry in entries {
+        let record = Record::Push {
+            entry: entry.clone(),
+        };
+        write_record(&mut file, &record, keys)?;
+    }
+    file.into_inner()?.sync_all()?;
+    fs::rename(&tmp, path)?;
+
+    Ok(BufWriter::new(OpenOptions::new().append(true).open(path)?))
+}
+
+/// Writes `record` as a line of JSON, sealed if there are `keys`.
+fn write_record(file: &mut impl Write, record: &Record, keys: Option<&Keyring>) -> io::Result<()> {
+    match keys {
+        Some(keys) => {
+            let sealed = keys.seal(&serde_json::to_vec(record)?)?;
+            let line = json!({ "sealed": STANDARD.encode(sealed) });
+            serde_json::to_writer(&mut *file, &line)?;
+        }
+        None => serde_json::to_writer(&mut *file, record)?,
+    }
+    file.write_all(b"\n")
+}
+
+/// What was read back from a log.
+#[derive(Debug, Default)]
+struct Replayed {
+    entries: Vec<StoredEntry>,
+    /// Number of lines in the log.
+    records: usize,
+    /// Some records are unencrypted or sealed with an older key.
+    stale: bool,
+    /// The last line is incomplete, appending to the log would corrupt it.
+    torn: bool,
+}
+
+/// Reads the entries back from a log, only a torn last line is skipped.
+fn replay(path: &Path, file: File, keys: Option<&Keyring>) -> io::Result<Replayed> {
+    let mut replayed = Replayed::default();
+    let entries = &mut replayed.entries;
+    let mut reader = BufReader::new(file);
+    let mut line = vec![];
+    for number in 1.. {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        replayed.records = number;
+        let last = reader.fill_buf()?.is_empty();
+        replayed.torn |= !line.ends_with(b"\n");
+        let record = match serde_json::from_slice(&line) {
+            Ok(Line::Plain(record)) => {
+                replayed.stale |= keys.is_some();
+                Ok(*record)
+            }
+            Ok(Line::Sealed { sealed }) => {
+                let Some(keys) = keys else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} is encrypted, but no storage key is set", path.display()),
+                    ));
+                };
+                let sealed = STANDARD
+                    .decode(sealed)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                replayed.stale |= !keys.is_current(&sealed);
+                serde_json::from_slice(&keys.open(&sealed)?)
+            }
+            Err(err) => Err(err),
+        };
+        match record {
+            Ok(Record::Push { entry }) => entries.push(entry),
+            Ok(Record::Remove { index }) if index < entries.len() => {
+                entries.remove(index);
+            }
+            Ok(Record::Replace { index, entry }) if index < entries.len() => {
+                entries[index] = entry;
+            }
+            // a crash while appending can leave a torn last line behind
+            Ok(Record::Remove { .. } | Record::Replace { .. }) | Err(_) if last => {
+                tracing::warn!("skipping torn record at {}:{}", path.display(), number);
+                replayed.torn = true;
+            }
+            Ok(Record::Remove { .. } | Record::Replace { .. }) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}:{}: no entry at the recorded index",
+                        path.display(),
+                        number
+                    ),
+                ));
+            }
+            Err(err) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: {}", path.display(), number, err),
+                ));
+            }
+        }
+    }
+    Ok(replayed)
+}
+
+/// Holds the contents of uploaded files outside of the entries, keyed by entry id.
+pub trait BlobStore: Send + Sync {
+    fn put(&self, id: Ulid, blob: &[u8]) -> io::Result<()>;
+
+    fn get(&self, id: Ulid) -> io::Result<Option<Bytes>>;
+
+    /// Removes the blob of `id`, if there is one.
+    fn remove(&self, id: Ulid) -> io::Result<()>;
+
+    /// Moves the blob of `from` to `id`, uploads are stored before their entry has
+    /// an id.
+    fn rename(&self, from: Ulid, id: Ulid) -> io::Result<()>;
+
+    /// Ids of all stored blobs.
+    fn ids(&self) -> io::Result<Vec<Ulid>>;
+
+    /// Removes what writes interrupted by a crash left behind, returns how much.
+    fn remove_partial(&self) -> io::Result<usize> {
+        Ok(0)
+    }
+
+    /// Re-encrypts the blob of `id` with the current storage key, returns whether
+    /// it was stored unencrypted or with an older key.
+    fn reseal(&self, _id: Ulid) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryBlobs {
+    blobs: RwLock<HashMap<Ulid, Bytes>>,
+}
+
+impl BlobStore for MemoryBlobs {
+    fn put(&self, id: Ulid, blob: &[u8]) -> io::Result<()> {
+        let blob = Bytes::copy_from_slice(blob);
+        self.blobs.write().unwrap().insert(id, blob);
+        Ok(())
+    }
+
+    fn get(&self, id: Ulid) -> io::Result<Option<Bytes>> {
+        Ok(self.blobs.read().unwrap().get(&id).cloned())
+    }
+
+    fn remove(&self, id: Ulid) -> io::Result<()> {
+        self.blobs.write().unwrap().remove(&id);
+        Ok(())
+    }
+
+    fn rename(&self, from: Ulid, id: Ulid) -> io::Result<()> {
+        let mut blobs = self.blobs.write().unwrap();
+        let blob = blobs.remove(&from).ok_or(io::ErrorKind::NotFound)?;
+        blobs.insert(id, blob);
+        Ok(())
+    }
+
+    fn ids(&self) -> io::Result<Vec<Ulid>> {
+        Ok(self.blobs.read().unwrap().keys().copied().collect())
+    }
+}
+
+/// Stores every blob in its own file named after the entry id, encrypted if there
+/// are storage keys.
+#[derive(Debug)]
+pub struct DiskBlobs {
+    dir: PathBuf,
+    keys: Option<Arc<Keyring>>,
+}
+
+impl DiskBlobs {
+    pub fn open(dir: impl Into<PathBuf>, keys: Option<Arc<Keyring>>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(DiskBlobs { dir, keys })
+    }
+
+    fn path(&self, id: Ulid) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+
+    fn read(&self, id: Ulid) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.path(id)) {
+            Ok(file) => Ok(Some(file)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The contents of a blob file. Files written before storage keys were set are
+    /// stored as they are.
+    fn unseal(&self, id: Ulid, file: Vec<u8>) -> io::Result<Vec<u8>> {
+        let Some(sealed) = file.strip_prefix(SEALED_BLOB_MAGIC) else {
+            return Ok(file);
+        };
+        match &self.keys {
+            Some(keys) => keys.open(sealed),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("blob {} is encrypted, but no storage key is set", id),
+            )),
+        }
+    }
+}
+
+impl BlobStore for DiskBlobs {
+    fn put(&self, id: Ulid, blob: &[u8]) -> io::Result<()> {
+        let tmp = self.path(id).with_extension("tmp");
+        match &self.keys {
+            Some(keys) => fs::write(&tmp, [SEALED_BLOB_MAGIC, &keys.seal(blob)?].concat())?,
+            None => fs::write(&tmp, blob)?,
+        }
+        fs::rename(tmp, self.path(id))
+    }
+
+    fn get(&self, id: Ulid) -> io::Result<Option<Bytes>> {
+        match self.read(id)? {
+            Some(file) => Ok(Some(self.unseal(id, file)?.into())),
+            None => Ok(None),
+        }
+    }
+
+    fn remove(&self, id: Ulid) -> io::Result<()> {
+        match fs::remove_file(self.path(id)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn rename(&self, from: Ulid, id: Ulid) -> io::Result<()> {
+        fs::rename(self.path(from), self.path(id))
+    }
+
+    fn ids(&self) -> io::Result<Vec<Ulid>> {
+        let mut ids = vec![];
+        for file in fs::read_dir(&self.dir)? {
+            if let Some(id) = file?.file_name().to_str().and_then(|s| s.parse().ok()) {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+
+    fn remove_partial(&self) -> io::Result<usize> {
+        let mut removed = 0;
+        for file in fs::read_dir(&self.dir)? {
+            let path = file?.path();
+            // may hold the plaintext of an upload, like the blob it was to become
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn reseal(&self, id: Ulid) -> io::Result<bool> {
+        let (Some(keys), Some(file)) = (&self.keys, self.read(id)?) else {
+            return Ok(false);
+        };
+        if file
+            .strip_prefix(SEALED_BLOB_MAGIC)
+            .is_some_and(|sealed| keys.is_current(sealed))
+        {
+            return Ok(false);
+        }
+        // the file is replaced atomically, readers get either version
+        self.put(id, &self.unseal(id, file)?)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn stored(data: &str) -> StoredEntry {
+        StoredEntry {
+            entry: test_support::entry(Ulid::new(), data),
+            password_hash: None,
+            revisions: vec![],
+        }
+    }
+
+    fn data(storage: &impl Storage) -> Vec<&str> {
+        let entries = storage.entries().iter();
+        entries.map(|e| e.entry.data.as_str()).collect()
+    }
+
+    #[test]
+    fn reopened_log_matches_entries_across_compactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.log");
+        let mut log = LogStorage::open(&path, None).unwrap();
+        for i in 0..200 {
+            log.push(stored(&format!("e{}", i))).unwrap();
+            if log.entries().len() > 10 {
+                log.remove(0).unwrap();
+            }
+        }
+        let mut replaced = log.entries()[3].clone();
+        replaced.entry.data = "replaced".to_owned();
+        log.replace(3, replaced).unwrap();
+        let expected: Vec<_> = data(&log).into_iter().map(str::to_owned).collect();
+        drop(log);
+
+        let log = LogStorage::open(&path, None).unwrap();
+        assert_eq!(data(&log), expected);
+        assert_eq!(data(&log).last(), Some(&"e199"));
+        assert_eq!(data(&log)[3], "replaced");
+    }
+
+    #[test]
+    fn removed_entries_stay_removed_after_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.log");
+        let mut log = LogStorage::open(&path, None).unwrap();
+        // enough churn for a remove to trigger a compaction
+        for i in 0..COMPACT_SLACK * 2 {
+            log.push(stored(&format!("e{}", i))).unwrap();
+            log.remove(0).unwrap();
+        }
+        log.push(stored("kept")).unwrap();
+        drop(log);
+
+        let log = LogStorage::open(&path, None).unwrap();
+        assert_eq!(data(&log), ["kept"]);
+    }
+
+    #[test]
+    fn entries_logged_by_older_versions_are_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.log");
+        let lines = [
+            r#"{"op":"push","entry":{"data":"first"}}"#,
+            r#"{"op":"push","entry":{"data":"second"}}"#,
+            r#"{"op":"push","entry":{"id":"01H8XGJWBWBAQ4Z2CDX5V0N6KM","data":"third","created_at":"2023-08-24T10:00:00Z","size":5,"content_type":"text/markdown","source":null}}"#,
+        ];
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+
+        let log = LogStorage::open(&path, None).unwrap();
+        let entries: Vec<_> = log.entries().iter().map(|e| &e.entry).collect();
+        assert_eq!(data(&log), ["first", "second", "third"]);
+        assert!(entries[0].id < entries[1].id && entries[1].id < entries[2].id);
+        assert_eq!(entries[1].size, "second".len());
+        assert_eq!(entries[1].content_type, "text/plain");
+        assert!(!entries[1].burn_after_read);
+        assert_eq!(entries[2].id.to_string(), "01H8XGJWBWBAQ4Z2CDX5V0N6KM");
+        assert_eq!(entries[2].content_type, "text/markdown");
+    }
+
+    #[test]
+    fn burned_entries_are_purged_from_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.log");
+        let mut log = LogStorage::open(&path, None).unwrap();
+        log.push(stored("kept")).unwrap();
+        let mut burned = stored("hunter2");
+        burned.entry.burn_after_read = true;
+        log.push(burned).unwrap();
+        log.remove(1).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("kept"));
+        assert!(!contents.contains("hunter2"));
+    }
+
+    #[test]
+    fn logs_are_resealed_after_opening_with_a_new_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.log");
+        let mut log = LogStorage::open(&path, None).unwrap();
+        log.push(stored("plaintext")).unwrap();
+        drop(log);
+
+        let keys = Arc::new(Keyring::parse(&"11".repeat(32)).unwrap());
+        let mut log = LogStorage::open(&path, Some(keys.clone())).unwrap();
+        // left as it is until resealed, new records are sealed right away
+        assert!(fs::read_to_string(&path).unwrap().contains("plaintext"));
+        log.push(stored("appended")).unwrap();
+        assert!(log.reseal().unwrap());
+        assert!(!log.reseal().unwrap());
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("plaintext") && !contents.contains("appended"));
+        drop(log);
+
+        let log = LogStorage::open(&path, Some(keys)).unwrap();
+        assert_eq!(data(&log), ["plaintext", "appended"]);
+    }
+
+    #[test]
+    fn partially_written_blobs_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let blobs = DiskBlobs::open(dir.path(), None).unwrap();
+        let id = Ulid::new();
+        blobs.put(id, b"kept").unwrap();
+        let partial = dir.path().join(format!("{}.tmp", Ulid::new()));
+        fs::write(&partial, b"plaintext").unwrap();
+
+        assert_eq!(blobs.remove_partial().unwrap(), 1);
+        assert!(!partial.exists());
+        assert_eq!(blobs.ids().unwrap(), [id]);
+        assert_eq!(blobs.get(id).unwrap().unwrap(), "kept");
+    }
+
+    #[test]
+    fn torn_last_line_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.log");
+        let mut log = LogStorage::open(&path, None).unwrap();
+        log.push(stored("first")).unwrap();
+        drop(log);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"push":{"entry":{"id":"#).unwrap();
+
+        let mut log = LogStorage::open(&path, None).unwrap();
+        assert_eq!(data(&log), ["first"]);
+        log.push(stored("second")).unwrap();
+        drop(log);
+
+        let log = LogStorage::open(&path, None).unwrap();
+        assert_eq!(data(&log), ["first", "second"]);
+    }
+
+    #[test]
+    fn corrupt_record_before_the_last_line_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clipboard.log");
+        let mut log = LogStorage::open(&path, None).unwrap();
+        log.push(stored("first")).unwrap();
+        drop(log);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"not json\n").unwrap();
+        let record = Record::Push {
+            entry: stored("second"),
+        };
+        write_record(&mut file, &record, None).unwrap();
+
+        let err = LogStorage::open(&path, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
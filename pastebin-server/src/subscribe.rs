@@ -0,0 +1,123 @@
+use crate::{api::validate_name, channels::Channels, error::ApiError, extract::Query};
+use async_stream::stream;
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    http::HeaderMap,
+    response::{
+        sse::{self, KeepAlive, Sse},
+        IntoResponse,
+    },
+};
+use futures::{Stream, StreamExt};
+use pastebin_core::{Event, Ulid};
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+#[derive(Debug, Deserialize)]
+pub struct SubscribeQuery {
+    /// Only push entries of this channel.
+    channel: Option<String>,
+    /// Resume cursor, first push the stored entries newer than this id.
+    after: Option<Ulid>,
+}
+
+/// Server-Sent Events stream of [`Event`]s, each tagged with the entry id so
+/// reconnecting clients resume through `Last-Event-ID`.
+pub async fn sse(
+    State(channels): State<Arc<Channels>>,
+    Query(query): Query<SubscribeQuery>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    if let Some(name) = &query.channel {
+        validate_name(name)?;
+    }
+    let after = query.after.or_else(|| {
+        headers
+            .get("last-event-id")
+            .and_then(|id| id.to_str().ok())
+            .and_then(|id| id.parse().ok())
+    });
+
+    let events = events(channels, query.channel, after).map(|event| {
+        sse::Event::default()
+            .id(event.entry.id.to_string())
+            .event("entry")
+            .json_data(&event)
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// WebSocket that sends every [`Event`] as a JSON text message.
+pub async fn ws(
+    State(channels): State<Arc<Channels>>,
+    Query(query): Query<SubscribeQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Result<impl IntoResponse, ApiError> {
+    if let Some(name) = &query.channel {
+        validate_name(name)?;
+    }
+    Ok(upgrade.on_upgrade(move |socket| forward(socket, channels, query)))
+}
+
+async fn forward(mut socket: WebSocket, channels: Arc<Channels>, query: SubscribeQuery) {
+    let events = events(channels, query.channel, query.after);
+    futures::pin_mut!(events);
+
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                let Some(event) = event else { break };
+                let json = serde_json::to_string(&event).expect("events serialize to JSON");
+                if socket.send(Message::Text(json)).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// The entries missed since `after` followed by every entry pasted from now on.
+fn events(
+    channels: Arc<Channels>,
+    channel: Option<String>,
+    after: Option<Ulid>,
+) -> impl Stream<Item = Event> {
+    stream! {
+        let (missed, mut receiver, mut last) = channels.subscribe(channel.as_deref(), after).await;
+        for event in missed {
+            last = event.entry.id;
+            yield event;
+        }
+
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    if channel.as_ref().is_some_and(|name| *name != event.channel)
+                        || event.entry.id <= last
+                    {
+                        continue;
+                    }
+                    last = event.entry.id;
+                    yield event;
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::debug!("subscriber lagged behind by {} entries", skipped);
+                    // catch up from storage, entries evicted in the meantime are lost
+                    for event in channels.events_after(channel.as_deref(), last) {
+                        last = event.entry.id;
+                        yield event;
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
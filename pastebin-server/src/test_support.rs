@@ -0,0 +1,27 @@
+//! Helpers shared by the tests of several modules.
+
+use chrono::Utc;
+use pastebin_core::{Entry, Ulid, DEFAULT_CONTENT_TYPE};
+
+/// A plain text entry holding `data`, change what a test needs with struct update
+/// syntax.
+pub fn entry(id: Ulid, data: &str) -> Entry {
+    Entry {
+        id,
+        data: data.to_owned(),
+        created_at: Utc::now(),
+        size: data.len(),
+        content_type: DEFAULT_CONTENT_TYPE.to_owned(),
+        source: None,
+        filename: None,
+        language: None,
+        blob: false,
+        expires_at: None,
+        burn_after_read: false,
+        protected: false,
+        encrypted: false,
+        pinned: false,
+        revision: 1,
+        updated_at: None,
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{
+    api::{password, validate, validate_name},
+    channels::{Channels, DEFAULT_CHANNEL},
+    error::ApiError,
+    extract::{Json, Path, Query},
+};
+use axum::{
+    body::{Body, Bytes},
+    extract::{FromRequest, Multipart, State},
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    response::IntoResponse,
+};
+use pastebin_core::{Entry, NewEntry, Ulid, ENCRYPTED_HEADER};
+use serde::Deserialize;
+use std::{fmt::Write, sync::Arc};
+
+/// Content type of uploads that do not name one.
+pub const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Metadata of an upload, from the query string or the text fields of a multipart form.
+#[derive(Debug, Default, Deserialize)]
+pub struct UploadQuery {
+    filename: Option<String>,
+    source: Option<String>,
+    language: Option<String>,
+    ttl: Option<u64>,
+    #[serde(default)]
+    burn_after_read: bool,
+    /// Taken from the password header or form field, never from the URL where it
+    /// would end up in logs.
+    #[serde(skip)]
+    pub password: Option<String>,
+}
+
+impl UploadQuery {
+    /// A new entry of `content_type` with this metadata and no `data`.
+    pub fn into_entry(self, content_type: String) -> NewEntry {
+        NewEntry {
+            content_type: Some(content_type),
+            source: self.source,
+            language: self.language,
+            filename: self
+                .filename
+                .map(|filename| base_name(&filename).to_owned()),
+            ttl: self.ttl,
+            burn_after_read: self.burn_after_read,
+            password: self.password,
+            ..NewEntry::default()
+        }
+    }
+}
+
+pub async fn upload(
+    State(channels): State<Arc<Channels>>,
+    Query(query): Query<UploadQuery>,
+    request: Request<Body>,
+) -> Result<impl IntoResponse, ApiError> {
+    store(&channels, DEFAULT_CHANNEL, query, request).await
+}
+
+pub async fn upload_channel(
+    State(channels): State<Arc<Channels>>,
+    Path(name): Path<String>,
+    Query(query): Query<UploadQuery>,
+    request: Request<Body>,
+) -> Result<impl IntoResponse, ApiError> {
+    validate_name(&name)?;
+    store(&channels, &name, query, request).await
+}
+
+/// Stores a `multipart/form-data` upload with the file in its `file` field, or the
+/// raw body of any other request.
+async fn store(
+    channels: &Channels,
+    name: &str,
+    mut query: UploadQuery,
+    request: Request<Body>,
+) -> Result<impl IntoResponse, ApiError> {
+    query.password = password(request.headers()).map(str::to_owned);
+    let content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+
+    let (content_type, blob) = if content_type
+        .as_deref()
+        .is_some_and(|value| value.starts_with("multipart/form-data"))
+    {
+        let multipart = Multipart::from_request(request, &()).await?;
+        read_form(multipart, &mut query).await?
+    } else {
+        let blob = Bytes::from_request(request, &()).await?;
+        (content_type, blob)
+    };
+
+    let entry = query.into_entry(content_type.unwrap_or_else(|| OCTET_STREAM.to_owned()));
+    validate(&entry, channels.max_ttl())?;
+    let entry = channels.upload(name, entry, blob).await?;
+    tracing::debug!(
+        "uploaded clipboard entry {} of {} bytes",
+        entry.id,
+        entry.size
+    );
+    Ok((StatusCode::CREATED, Json(entry)))
+}
+
+/// Returns the content type and contents of the `file` field, the other fields
+/// override the metadata in `query`.
+async fn read_form(
+    mut multipart: Multipart,
+    query: &mut UploadQuery,
+) -> Result<(Option<String>, Bytes), ApiError> {
+    let mut file = None;
+    while let Some(field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_owned();
+        match name.as_str() {
+            "file" => {
+                if file.is_some() {
+                    return Err(ApiError::bad_request("only one file may be uploaded"));
+                }
+                if query.filename.is_none() {
+                    query.filename = field.file_name().map(str::to_owned);
+                }
+                let content_type = field.content_type().map(str::to_owned);
+                file = Some((content_type, field.bytes().await?));
+            }
+            "filename" => query.filename = Some(field.text().await?),
+            "source" => query.source = Some(field.text().await?),
+            "language" => query.language = Some(field.text().await?),
+            "password" => query.password = Some(field.text().await?),
+            "ttl" => {
+                let ttl = field.text().await?;
+                let ttl = ttl
+                    .trim()
+                    .parse()
+                    .map_err(|_| ApiError::bad_request(format!("invalid ttl {:?}", ttl)))?;
+                query.ttl = Some(ttl);
+            }
+            "burn_after_read" => {
+                let burn = field.text().await?;
+                query.burn_after_read = match burn.trim() {
+                    "true" | "on" | "1" => true,
+                    "false" | "off" | "0" => false,
+                    _ => {
+                        return Err(ApiError::bad_request(format!(
+                            "invalid burn_after_read {:?}",
+                            burn
+                        )))
+                    }
+                };
+            }
+            _ => return Err(ApiError::bad_request(format!("unknown field {:?}", name))),
+        }
+    }
+    file.ok_or_else(|| ApiError::bad_request("missing file field"))
+}
+
+/// Serves the contents of an entry with its content type, uploaded files as downloads.
+pub async fn get_content(
+    State(channels): State<Arc<Channels>>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    tracing::debug!("fetching contents of clipboard entry {}", id);
+    let (entry, contents) = channels
+        .read_content(id, password(&headers))
+        .await?
+        .ok_or_else(ApiError::not_found)?;
+
+    Ok(content_response(&entry, contents))
+}
+
+/// The contents of `entry` with headers that keep browsers from running them.
+pub fn content_response(entry: &Entry, contents: Bytes) -> impl IntoResponse {
+    let mut headers = HeaderMap::new();
+    let content_type = HeaderValue::from_str(&entry.content_type)
+        .unwrap_or_else(|_| HeaderValue::from_static(OCTET_STREAM));
+    headers.insert(header::CONTENT_TYPE, content_type);
+    let disposition = match (&entry.filename, entry.blob) {
+        (Some(filename), true) => content_disposition("attachment", filename),
+        (Some(filename), false) => content_disposition("inline", filename),
+        (None, true) => "attachment".to_owned(),
+        (None, false) => "inline".to_owned(),
+    };
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_str(&disposition).expect("the disposition is ASCII"),
+    );
+    // the contents are whatever was pasted, keep browsers from running them in our origin
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static("sandbox"),
+    );
+    if entry.encrypted {
+        headers.insert(ENCRYPTED_HEADER, HeaderValue::from_static("true"));
+    }
+    (headers, contents)
+}
+
+/// A `Content-Disposition` naming `filename`, with an ASCII fallback for old clients
+/// and the exact name percent-encoded as `filename*` (RFC 6266).
+fn content_disposition(kind: &str, filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    let mut encoded = String::new();
+    for byte in filename.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            write!(encoded, "%{:02X}", byte).unwrap();
+        }
+    }
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        kind, fallback, encoded
+    )
+}
+
+/// Strips any directories from a client supplied file name.
+fn base_name(filename: &str) -> &str {
+    filename.rsplit(['/', '\\']).next().unwrap_or(filename)
+}
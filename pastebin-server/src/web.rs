@@ -0,0 +1,415 @@
+use crate::{
+    api::{password, validate, validate_name},
+    channels::{Channels, ReadError, DEFAULT_CHANNEL},
+    clipboard::AddError,
+    error::ApiError,
+    highlight, markdown, upload,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    Form,
+};
+use maud::{html, Markup, PreEscaped, DOCTYPE};
+use pastebin_core::{Entry, NewEntry, Ulid};
+use serde::Deserialize;
+use std::{io, sync::Arc};
+
+/// Rendered Markdown is sanitized, this keeps scripts from running should anything
+/// get through anyway.
+const RENDERED_CSP: &str = "default-src 'none'; style-src 'unsafe-inline'; img-src * data:";
+
+/// Characters of an entry shown on the index page.
+const PREVIEW_LEN: usize = 280;
+
+const STYLE: &str = "
+body { font-family: sans-serif; max-width: 60rem; margin: 0 auto; padding: 1rem; }
+header { display: flex; gap: 1rem; align-items: baseline; }
+textarea { width: 100%; min-height: 12rem; font-family: monospace; box-sizing: border-box; }
+pre { background: #f4f4f4; padding: .5rem; overflow-x: auto; white-space: pre-wrap; }
+article { border-top: 1px solid #ddd; padding: .5rem 0; }
+.meta { color: #666; font-size: .9rem; }
+.error { color: #b00; }
+table.code { border-collapse: collapse; width: 100%; background: #f4f4f4; font-family: monospace; }
+table.code td { white-space: pre-wrap; padding: 0 .5rem; vertical-align: top; }
+td.ln { user-select: none; text-align: right; width: 1%; color: #999; }
+td.ln a { color: inherit; text-decoration: none; }
+tr:target, tr.marked { background: #fff8c5; }
+";
+
+/// Copies the `/raw` contents of an entry, the buttons stay hidden without JavaScript,
+/// and marks line ranges like `#L10-L20`, which shift-clicking a line number selects.
+const SCRIPT: &str = "
+for (const button of document.querySelectorAll('button[data-copy]')) {
+  button.hidden = false;
+  button.addEventListener('click', async () => {
+    const response = await fetch('/raw/' + button.dataset.copy);
+    await navigator.clipboard.writeText(await response.text());
+    button.textContent = 'Copied';
+  });
+}
+function markedLines() {
+  const match = location.hash.match(/^#L(\\d+)(?:-L(\\d+))?$/);
+  return match && [+match[1], +(match[2] || match[1])];
+}
+function markLines() {
+  for (const row of document.querySelectorAll('tr.marked')) row.classList.remove('marked');
+  const lines = markedLines();
+  if (!lines) return;
+  for (let n = lines[0]; n <= lines[1]; n++) document.getElementById('L' + n)?.classList.add('marked');
+  document.getElementById('L' + lines[0])?.scrollIntoView();
+}
+for (const link of document.querySelectorAll('td.ln a')) {
+  link.addEventListener('click', event => {
+    const lines = markedLines();
+    if (!event.shiftKey || !lines) return;
+    event.preventDefault();
+    const n = +link.textContent;
+    location.hash = '#L' + Math.min(lines[0], n) + '-L' + Math.max(lines[1], n);
+  });
+}
+window.addEventListener('hashchange', markLines);
+markLines();
+";
+
+#[derive(Debug, Deserialize)]
+pub struct IndexQuery {
+    channel: Option<String>,
+}
+
+/// Lists the entries of a channel, newest first, below a paste form.
+pub async fn index(
+    State(channels): State<Arc<Channels>>,
+    Query(query): Query<IndexQuery>,
+) -> Result<impl IntoResponse, WebError> {
+    let channel = query.channel.unwrap_or_else(|| DEFAULT_CHANNEL.to_owned());
+    validate_name(&channel)?;
+    let entries = match channels.get(&channel) {
+        Some(clipboard) => clipboard.read().unwrap().get_entries(),
+        None => vec![],
+    };
+    let names: Vec<_> = channels.list().into_iter().map(|info| info.name).collect();
+
+    Ok(page(
+        &channel,
+        html! {
+            form method="post" action="/web/paste" {
+                input type="hidden" name="channel" value=(channel);
+                textarea name="data" required placeholder="Paste something" {}
+                p {
+                    label { "Language " input type="text" name="language" size="10" placeholder="detect"; }
+                    " "
+                    label { "Expires after " input type="number" name="ttl" min="1" placeholder="seconds"; }
+                    " "
+                    label { input type="checkbox" name="burn_after_read"; " Burn after read" }
+                    " "
+                    label { "Password " input type="password" name="password" size="10" placeholder="none"; }
+                    " "
+                    button type="submit" { "Paste to " (channel) }
+                }
+            }
+            @if names.len() > 1 {
+                nav {
+                    "Channels: "
+                    @for name in &names {
+                        a href={ "/?channel=" (name) } { (name) } " "
+                    }
+                }
+            }
+            @if entries.is_empty() {
+                p.meta { "No entries yet." }
+            }
+            @for entry in entries.iter().rev() {
+                article {
+                    div.meta {
+                        a href={ "/view/" (entry.id) } { (entry.id) } " · " (describe(entry))
+                        @if !entry.protected {
+                            " "
+                            button type="button" data-copy=(entry.id) hidden { "Copy" }
+                        }
+                    }
+                    @if entry.protected {
+                        p { a href={ "/view/" (entry.id) } { "Password protected" } }
+                    } @else if entry.encrypted {
+                        p.meta { "Encrypted" }
+                    } @else if entry.blob {
+                        p { a href={ "/entries/" (entry.id) "/content" } { "Download" } }
+                    } @else {
+                        pre { (preview(&entry.data)) }
+                    }
+                }
+            }
+        },
+    ))
+}
+
+/// Shows a single entry. Burn-after-read and protected entries are only revealed
+/// through a form, so link previews and crawlers do not burn them.
+pub async fn view(
+    State(channels): State<Arc<Channels>>,
+    Path(id): Path<Ulid>,
+) -> Result<impl IntoResponse, WebError> {
+    let entry = channels.peek(id).ok_or_else(ApiError::not_found)?;
+    if entry.burn_after_read || entry.protected {
+        return Ok(page(
+            "Locked",
+            html! {
+                @if entry.burn_after_read {
+                    p { "This entry is deleted once it is viewed." }
+                }
+                form method="post" action={ "/view/" (id) } {
+                    @if entry.protected {
+                        label { "Password " input type="password" name="password" required autofocus; }
+                        " "
+                    }
+                    button type="submit" {
+                        @if entry.blob { "Download it" } @else { "Show it" }
+                    }
+                }
+            },
+        ));
+    }
+    Ok(entry_page(&entry))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevealForm {
+    password: Option<String>,
+}
+
+/// Reveals a burn-after-read or protected entry, uploaded files are downloaded
+/// right away as they could not be fetched again.
+pub async fn reveal(
+    State(channels): State<Arc<Channels>>,
+    Path(id): Path<Ulid>,
+    Form(form): Form<RevealForm>,
+) -> Result<Response, WebError> {
+    let (entry, contents) = channels
+        .read_content(id, form.password.as_deref())
+        .await?
+        .ok_or_else(ApiError::not_found)?;
+    if entry.blob {
+        return Ok(upload::content_response(&entry, contents).into_response());
+    }
+    Ok(entry_page(&entry).into_response())
+}
+
+fn entry_page(entry: &Entry) -> Html<String> {
+    page(
+        &entry.id.to_string(),
+        html! {
+            @if entry.burn_after_read {
+                p.meta { (describe(entry)) }
+                p.error { "This entry has been deleted, it cannot be viewed again." }
+            } @else if entry.protected {
+                // the other views need the password again
+                p.meta { (describe(entry)) " · " (highlight::syntax(entry).name) }
+            } @else {
+                p.meta {
+                    (describe(entry)) " · "
+                    @if !entry.blob && !entry.encrypted {
+                        (highlight::syntax(entry).name) " · "
+                    }
+                    @if is_markdown(entry) && !entry.encrypted {
+                        a href={ "/entries/" (entry.id) "/rendered" } { "Rendered" } " · "
+                    }
+                    a href={ "/raw/" (entry.id) } { "Raw" } " · "
+                    a href={ "/entries/" (entry.id) "/content" } { "Download" }
+                    " "
+                    button type="button" data-copy=(entry.id) hidden { "Copy" }
+                }
+            }
+            @if entry.encrypted {
+                p.meta { "Encrypted, decrypt it with the pastebin CLI and the key it was pasted with." }
+                pre { (entry.data) }
+            } @else if !entry.blob {
+                (highlight::render(entry))
+            } @else if entry.content_type.starts_with("image/") {
+                img src={ "/entries/" (entry.id) "/content" } alt=[entry.filename.as_deref()];
+            }
+        },
+    )
+}
+
+/// Renders a Markdown entry as sanitized HTML.
+pub async fn rendered(
+    State(channels): State<Arc<Channels>>,
+    Path(id): Path<Ulid>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, WebError> {
+    let (entry, contents) = channels
+        .read_content(id, password(&headers))
+        .await?
+        .ok_or_else(ApiError::not_found)?;
+    let markdown = std::str::from_utf8(&contents).map_err(|_| {
+        ApiError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "only text entries can be rendered",
+        )
+    })?;
+
+    let body = html! { article { (PreEscaped(markdown::render(markdown))) } };
+    let page = layout(&entry.id.to_string(), body, highlight::classed_css(), false);
+    Ok(([(header::CONTENT_SECURITY_POLICY, RENDERED_CSP)], page))
+}
+
+fn is_markdown(entry: &Entry) -> bool {
+    entry.content_type == "text/markdown" || highlight::syntax(entry).name == "Markdown"
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasteForm {
+    channel: String,
+    data: String,
+    #[serde(default)]
+    language: String,
+    #[serde(default)]
+    password: String,
+    #[serde(default)]
+    ttl: String,
+    burn_after_read: Option<String>,
+}
+
+/// Adds an entry from the paste form and redirects to it.
+pub async fn paste(
+    State(channels): State<Arc<Channels>>,
+    Form(form): Form<PasteForm>,
+) -> Result<Response, WebError> {
+    validate_name(&form.channel)?;
+    let ttl = match form.ttl.trim() {
+        "" => None,
+        ttl => Some(
+            ttl.parse()
+                .map_err(|_| ApiError::bad_request(format!("invalid ttl {:?}", ttl)))?,
+        ),
+    };
+    let entry = NewEntry {
+        // browsers submit text areas with CRLF line breaks
+        data: form.data.replace("\r\n", "\n"),
+        language: Some(form.language.trim().to_owned()).filter(|language| !language.is_empty()),
+        ttl,
+        burn_after_read: form.burn_after_read.is_some(),
+        password: Some(form.password).filter(|password| !password.is_empty()),
+        ..NewEntry::default()
+    };
+    validate(&entry, channels.max_ttl())?;
+    let entry = channels.paste(&form.channel, entry).await?;
+    tracing::debug!("added clipboard entry {} from the web", entry.id);
+
+    if entry.burn_after_read {
+        // the entry is not listed, the link to it is all the user gets
+        return Ok(page(
+            "Pasted",
+            html! {
+                p { "Share this link, the entry is deleted once it is viewed:" }
+                p { a href={ "/view/" (entry.id) } { "/view/" (entry.id) } }
+            },
+        )
+        .into_response());
+    }
+    Ok(Redirect::to(&format!("/view/{}", entry.id)).into_response())
+}
+
+/// One line summary of an entry's metadata.
+fn describe(entry: &Entry) -> String {
+    let mut parts = vec![
+        entry.created_at.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        entry.content_type.clone(),
+        format!("{} bytes", entry.size),
+    ];
+    if let Some(filename) = &entry.filename {
+        parts.push(filename.clone());
+    }
+    if let Some(source) = &entry.source {
+        parts.push(format!("from {}", source));
+    }
+    if entry.pinned {
+        parts.push("pinned".to_owned());
+    }
+    if entry.revision > 1 {
+        parts.push(format!("revision {}", entry.revision));
+    }
+    if let Some(expires_at) = entry.expires_at {
+        parts.push(format!(
+            "expires {}",
+            expires_at.format("%Y-%m-%d %H:%M UTC")
+        ));
+    }
+    parts.join(" · ")
+}
+
+fn preview(data: &str) -> String {
+    match data.char_indices().nth(PREVIEW_LEN) {
+        Some((end, _)) => format!("{}…", &data[..end]),
+        None => data.to_owned(),
+    }
+}
+
+fn page(title: &str, body: Markup) -> Html<String> {
+    layout(title, body, "", true)
+}
+
+/// The frame of every page, `style` is added to the style sheet and `script` enables
+/// the copy buttons and line marking.
+fn layout(title: &str, body: Markup, style: &str, script: bool) -> Html<String> {
+    let markup = html! {
+        (DOCTYPE)
+        html lang="en" {
+            head {
+                meta charset="utf-8";
+                meta name="viewport" content="width=device-width, initial-scale=1";
+                title { (title) " · pastebin" }
+                style { (PreEscaped(STYLE)) (PreEscaped(style)) }
+            }
+            body {
+                header {
+                    h1 { a href="/" { "pastebin" } }
+                    span.meta { (title) }
+                }
+                main { (body) }
+                @if script {
+                    script { (PreEscaped(SCRIPT)) }
+                }
+            }
+        }
+    };
+    Html(markup.into_string())
+}
+
+/// An [`ApiError`] rendered as an HTML page.
+pub struct WebError(ApiError);
+
+impl From<ApiError> for WebError {
+    fn from(err: ApiError) -> Self {
+        WebError(err)
+    }
+}
+
+impl From<ReadError> for WebError {
+    fn from(err: ReadError) -> Self {
+        WebError(err.into())
+    }
+}
+
+impl From<AddError> for WebError {
+    fn from(err: AddError) -> Self {
+        WebError(err.into())
+    }
+}
+
+impl From<io::Error> for WebError {
+    fn from(err: io::Error) -> Self {
+        WebError(err.into())
+    }
+}
+
+impl IntoResponse for WebError {
+    fn into_response(self) -> Response {
+        let body = page(
+            self.0.status().canonical_reason().unwrap_or("Error"),
+            html! { p.error { (self.0.message()) } },
+        );
+        (self.0.status(), [(header::CACHE_CONTROL, "no-store")], body).into_response()
+    }
+}
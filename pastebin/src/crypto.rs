@@ -0,0 +1,158 @@
+//! End-to-end encryption of entries, the server only ever sees ciphertext.
+//!
+//! Encrypted data is pasted as base64 of a version byte, a key kind byte, the salt
+//! of the passphrase if the key was derived from one, a nonce and the XChaCha20-Poly1305
+//! ciphertext. The header bytes are authenticated along with the ciphertext.
+
+use anyhow::{bail, Context, Result};
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use std::{fs, path::Path};
+
+const VERSION: u8 = 1;
+const KEY_FILE: u8 = 0;
+const PASSPHRASE: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Where the key of an entry comes from.
+pub enum Secret {
+    /// 32 bytes read from a key file.
+    Key([u8; 32]),
+    /// A passphrase, each entry gets its own key derived with a random salt.
+    Passphrase(String),
+}
+
+impl Secret {
+    /// Reads a key file holding 32 bytes as 64 hex digits, e.g. from `openssl rand -hex 32`.
+    pub fn from_key_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut key = [0; 32];
+        hex::decode_to_slice(contents.trim(), &mut key)
+            .with_context(|| format!("{} does not hold a key of 64 hex digits", path.display()))?;
+        Ok(Secret::Key(key))
+    }
+}
+
+pub fn encrypt(secret: &Secret, plaintext: &[u8]) -> Result<String> {
+    let mut header = vec![VERSION];
+    let key = match secret {
+        Secret::Key(key) => {
+            header.push(KEY_FILE);
+            *key
+        }
+        Secret::Passphrase(passphrase) => {
+            let mut salt = [0; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            header.push(PASSPHRASE);
+            header.extend_from_slice(&salt);
+            derive_key(passphrase, &salt)?
+        }
+    };
+
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let payload = Payload {
+        msg: plaintext,
+        aad: &header,
+    };
+    let ciphertext = XChaCha20Poly1305::new(&key.into())
+        .encrypt(&nonce, payload)
+        .map_err(|_| anyhow::anyhow!("encryption failed"))?;
+
+    let mut sealed = header;
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(STANDARD.encode(sealed))
+}
+
+pub fn decrypt(secret: &Secret, data: &str) -> Result<Vec<u8>> {
+    let sealed = STANDARD
+        .decode(data.trim())
+        .context("entry is not valid encrypted data")?;
+    let (header_len, key) = match (sealed.first(), sealed.get(1), secret) {
+        (Some(&VERSION), Some(&KEY_FILE), Secret::Key(key)) => (2, *key),
+        (Some(&VERSION), Some(&PASSPHRASE), Secret::Passphrase(passphrase)) => {
+            let Some(salt) = sealed.get(2..2 + SALT_LEN) else {
+                bail!("entry is not valid encrypted data");
+            };
+            (2 + SALT_LEN, derive_key(passphrase, salt)?)
+        }
+        (Some(&VERSION), Some(&KEY_FILE), _) => bail!("entry was encrypted with a key file"),
+        (Some(&VERSION), Some(&PASSPHRASE), _) => bail!("entry was encrypted with a passphrase"),
+        _ => bail!("entry is not valid encrypted data"),
+    };
+    if sealed.len() < header_len + NONCE_LEN {
+        bail!("entry is not valid encrypted data");
+    }
+
+    let (header, rest) = sealed.split_at(header_len);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let payload = Payload {
+        msg: ciphertext,
+        aad: header,
+    };
+    XChaCha20Poly1305::new(&key.into())
+        .decrypt(XNonce::from_slice(nonce), payload)
+        .map_err(|_| anyhow::anyhow!("failed to decrypt the entry, wrong key or passphrase"))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32]> {
+    let mut key = [0; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| anyhow::anyhow!("failed to derive the key: {}", err))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_round_trip_with_a_key() {
+        let secret = Secret::Key([7; 32]);
+        let sealed = encrypt(&secret, b"hello").unwrap();
+        assert_eq!(decrypt(&secret, &sealed).unwrap(), b"hello");
+        // every entry gets its own nonce
+        assert_ne!(encrypt(&secret, b"hello").unwrap(), sealed);
+    }
+
+    #[test]
+    fn entries_round_trip_with_a_passphrase() {
+        let secret = Secret::Passphrase("hunter2".to_string());
+        let sealed = encrypt(&secret, b"hello").unwrap();
+        assert_eq!(decrypt(&secret, &sealed).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn the_wrong_secret_does_not_decrypt() {
+        let sealed = encrypt(&Secret::Key([7; 32]), b"hello").unwrap();
+        assert!(decrypt(&Secret::Key([8; 32]), &sealed).is_err());
+        assert!(decrypt(&Secret::Passphrase("hunter2".to_string()), &sealed).is_err());
+
+        let sealed = encrypt(&Secret::Passphrase("hunter2".to_string()), b"hello").unwrap();
+        assert!(decrypt(&Secret::Passphrase("hunter3".to_string()), &sealed).is_err());
+        assert!(decrypt(&Secret::Key([7; 32]), &sealed).is_err());
+    }
+
+    #[test]
+    fn tampered_entries_do_not_decrypt() {
+        let secret = Secret::Key([7; 32]);
+        let sealed = STANDARD
+            .decode(encrypt(&secret, b"hello").unwrap())
+            .unwrap();
+        for i in 0..sealed.len() {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 1;
+            assert!(decrypt(&secret, &STANDARD.encode(&tampered)).is_err());
+        }
+        let truncated = STANDARD.encode(&sealed[..2 + NONCE_LEN - 1]);
+        assert!(decrypt(&secret, &truncated).is_err());
+        assert!(decrypt(&secret, "not base64!").is_err());
+    }
+}
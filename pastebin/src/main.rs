@@ -1,3 +1,471 @@
-fn main() {
-    println!("Hello, world!");
+mod crypto;
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use crypto::Secret;
+use pastebin_core::{EntryUpdate, NewEntry, PastebinClient, Ulid};
+use serde::Deserialize;
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+const DEFAULT_SERVER: &str = "http://localhost:3000";
+
+/// Copy and paste via a pastebin-server clipboard
+///
+/// The server, channel and token can also be set in the config file, flags and
+/// environment variables take precedence over it.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    /// TOML file to read the server, channel and token from
+    #[arg(long, env = "PASTEBIN_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Base URL of the pastebin server [default: http://localhost:3000]
+    #[arg(short, long, env = "PASTEBIN_SERVER")]
+    server: Option<String>,
+
+    /// Channel to paste to and copy from instead of the default one
+    #[arg(short, long, env = "PASTEBIN_CHANNEL")]
+    channel: Option<String>,
+
+    /// API token, needed if the server requires authentication
+    #[arg(long, env = "PASTEBIN_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    #[command(flatten)]
+    keys: Keys,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Options of the config file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    server: Option<String>,
+    channel: Option<String>,
+    token: Option<String>,
+}
+
+impl ConfigFile {
+    fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("failed to parse {}", path.display()))
+    }
+}
+
+/// Where the key of encrypted entries comes from, a passphrase is prompted for if
+/// neither is given.
+#[derive(Debug, Args)]
+struct Keys {
+    /// File holding the key of encrypted entries as 64 hex digits
+    #[arg(long, env = "PASTEBIN_KEY_FILE")]
+    key_file: Option<PathBuf>,
+
+    /// Passphrase to derive the key of encrypted entries from
+    #[arg(
+        long,
+        env = "PASTEBIN_PASSPHRASE",
+        hide_env_values = true,
+        conflicts_with = "key_file"
+    )]
+    passphrase: Option<String>,
+}
+
+impl Keys {
+    /// The key to use, `confirm` asks for a prompted passphrase twice.
+    fn secret(&self, confirm: bool) -> Result<Secret> {
+        if let Some(path) = &self.key_file {
+            return Secret::from_key_file(path);
+        }
+        if let Some(passphrase) = &self.passphrase {
+            return Ok(Secret::Passphrase(passphrase.clone()));
+        }
+        let passphrase =
+            rpassword::prompt_password("Passphrase: ").context("failed to read the passphrase")?;
+        if confirm {
+            let again = rpassword::prompt_password("Repeat passphrase: ")
+                .context("failed to read the passphrase")?;
+            if again != passphrase {
+                bail!("the passphrases do not match");
+            }
+        }
+        if passphrase.is_empty() {
+            bail!("the passphrase must not be empty");
+        }
+        Ok(Secret::Passphrase(passphrase))
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Paste a file, or stdin if no file is given, into the clipboard and print its id
+    ///
+    /// Data that is not valid UTF-8 is uploaded as a binary file, unless it is encrypted.
+    Paste {
+        /// File to paste
+        file: Option<PathBuf>,
+
+        /// MIME type of the pasted data [default: text/plain]
+        #[arg(short = 't', long)]
+        content_type: Option<String>,
+
+        /// Label of where the entry was pasted from [default: this host's name]
+        #[arg(long)]
+        source: Option<String>,
+
+        /// Language to highlight the entry as, e.g. rust [default: detected by the server]
+        #[arg(short, long)]
+        language: Option<String>,
+
+        /// Seconds after which the entry expires [default: the server's default]
+        #[arg(long)]
+        ttl: Option<u64>,
+
+        /// Delete the entry once it was copied by id
+        #[arg(long)]
+        burn_after_read: bool,
+
+        /// Password needed to copy the entry
+        #[arg(long, env = "PASTEBIN_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+
+        /// Encrypt the data before it is sent, the server only gets to see ciphertext
+        #[arg(short, long, conflicts_with_all = ["content_type", "language"])]
+        encrypt: bool,
+    },
+    /// Print an entry of the clipboard
+    ///
+    /// Encrypted entries are decrypted with the key from --key-file or --passphrase.
+    Copy {
+        /// Print the Nth newest entry instead of the newest one
+        #[arg(short, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        n: u64,
+
+        /// Print the entry with this id
+        #[arg(long, conflicts_with = "n")]
+        id: Option<Ulid>,
+
+        /// Password of a protected entry
+        #[arg(long, env = "PASTEBIN_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// Replace an entry with the contents of a file, or stdin if no file is given
+    ///
+    /// The earlier contents are kept as revisions. Encrypted entries stay encrypted.
+    Revise {
+        /// Id of the entry
+        id: Ulid,
+
+        /// File to read the new contents from
+        file: Option<PathBuf>,
+
+        /// Password of a protected entry
+        #[arg(long, env = "PASTEBIN_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// List the revisions of an entry, oldest first
+    History {
+        /// Id of the entry
+        id: Ulid,
+
+        /// Password of a protected entry
+        #[arg(long, env = "PASTEBIN_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// Print a unified diff between two revisions of an entry
+    Diff {
+        /// Id of the entry
+        id: Ulid,
+
+        /// Revision to diff from [default: the one before --to]
+        #[arg(long)]
+        from: Option<u32>,
+
+        /// Revision to diff to [default: the latest one]
+        #[arg(long)]
+        to: Option<u32>,
+
+        /// Password of a protected entry
+        #[arg(long, env = "PASTEBIN_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// Delete an entry from the clipboard
+    Delete {
+        /// Id of the entry
+        id: Ulid,
+    },
+    /// Pin an entry, so it is never evicted
+    Pin {
+        /// Id of the entry
+        id: Ulid,
+    },
+    /// Unpin an entry
+    Unpin {
+        /// Id of the entry
+        id: Ulid,
+    },
+    /// List the channels of the server
+    Channels,
+    /// Search the entries of the channel, or of every channel if none is given
+    ///
+    /// Prints the id, channel and an excerpt of every entry found. Put phrases that
+    /// have to match as a whole in double quotes.
+    Search {
+        /// Words to search for
+        #[arg(required = true)]
+        query: Vec<String>,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) if err.chain().len() > 1 => {
+            eprintln!("pastebin: {}: {}", err, err.root_cause());
+            ExitCode::FAILURE
+        }
+        Err(err) => {
+            eprintln!("pastebin: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let file = match &cli.config {
+        Some(path) => ConfigFile::read(path)?,
+        None => ConfigFile::default(),
+    };
+    let server = cli.server.or(file.server);
+    let mut client = PastebinClient::new(server.unwrap_or_else(|| DEFAULT_SERVER.to_owned()));
+    if let Some(channel) = cli.channel.or(file.channel) {
+        client = client.channel(channel);
+    }
+    if let Some(token) = cli.token.or(file.token) {
+        client = client.token(token);
+    }
+
+    match cli.command {
+        Command::Paste {
+            file,
+            content_type,
+            source,
+            language,
+            ttl,
+            burn_after_read,
+            password,
+            encrypt,
+        } => {
+            let entry = NewEntry {
+                content_type,
+                source: source.or_else(|| gethostname::gethostname().into_string().ok()),
+                language,
+                ttl,
+                burn_after_read,
+                password,
+                ..NewEntry::default()
+            };
+            let secret = if encrypt {
+                Some(cli.keys.secret(true)?)
+            } else {
+                None
+            };
+            paste(&client, file, entry, secret.as_ref()).await
+        }
+        Command::Copy { n, id, password } => {
+            if let Some(password) = password {
+                client = client.password(password);
+            }
+            match id {
+                Some(id) => copy_id(&client, &cli.keys, id).await,
+                None => copy(&client, &cli.keys, n).await,
+            }
+        }
+        Command::Revise { id, file, password } => {
+            if let Some(password) = password {
+                client = client.password(password);
+            }
+            revise(&client, &cli.keys, id, file).await
+        }
+        Command::History { id, password } => {
+            if let Some(password) = password {
+                client = client.password(password);
+            }
+            history(&client, id).await
+        }
+        Command::Diff {
+            id,
+            from,
+            to,
+            password,
+        } => {
+            if let Some(password) = password {
+                client = client.password(password);
+            }
+            print_data(client.diff(id, from, to).await?.as_bytes())
+        }
+        Command::Delete { id } => Ok(client.delete(id).await?),
+        Command::Pin { id } => {
+            client.pin(id, true).await?;
+            Ok(())
+        }
+        Command::Unpin { id } => {
+            client.pin(id, false).await?;
+            Ok(())
+        }
+        Command::Channels => channels(&client).await,
+        Command::Search { query } => search(&client, &query.join(" ")).await,
+    }
+}
+
+async fn paste(
+    client: &PastebinClient,
+    file: Option<PathBuf>,
+    mut entry: NewEntry,
+    secret: Option<&Secret>,
+) -> Result<()> {
+    let data = read_input(file.as_deref())?;
+    if let Some(secret) = secret {
+        // the file name would tell the server what the entry is about
+        let data = crypto::encrypt(secret, &data)?;
+        let entry = client
+            .paste(&NewEntry {
+                data,
+                encrypted: true,
+                ..entry
+            })
+            .await?;
+        println!("{}", entry.id);
+        return Ok(());
+    }
+    entry.filename = file
+        .as_deref()
+        .and_then(Path::file_name)
+        .and_then(|name| name.to_str())
+        .map(str::to_owned);
+
+    let entry = match String::from_utf8(data) {
+        Ok(data) => client.paste(&NewEntry { data, ..entry }).await?,
+        Err(err) => client.upload(&entry, err.into_bytes()).await?,
+    };
+    println!("{}", entry.id);
+    Ok(())
+}
+
+async fn revise(
+    client: &PastebinClient,
+    keys: &Keys,
+    id: Ulid,
+    file: Option<PathBuf>,
+) -> Result<()> {
+    // unlike fetching the entry, listing its revisions never burns it
+    let revisions = client.revisions(id).await?;
+    let encrypted = revisions.last().is_some_and(|revision| revision.encrypted);
+    let data = read_input(file.as_deref())?;
+    let data = if encrypted {
+        crypto::encrypt(&keys.secret(true)?, &data)?
+    } else {
+        String::from_utf8(data).context("only text entries can be revised")?
+    };
+    let entry = client
+        .revise(
+            id,
+            &EntryUpdate {
+                data,
+                ..EntryUpdate::default()
+            },
+        )
+        .await?;
+    println!("{}", entry.revision);
+    Ok(())
+}
+
+async fn history(client: &PastebinClient, id: Ulid) -> Result<()> {
+    for revision in client.revisions(id).await? {
+        println!(
+            "{}\t{}\t{}",
+            revision.revision, revision.created_at, revision.size
+        );
+    }
+    Ok(())
+}
+
+/// Reads the contents of `file`, or of stdin if none is given.
+fn read_input(file: Option<&Path>) -> Result<Vec<u8>> {
+    match file {
+        Some(path) => fs::read(path).with_context(|| format!("failed to read {}", path.display())),
+        None => {
+            let mut data = vec![];
+            io::stdin()
+                .read_to_end(&mut data)
+                .context("failed to read stdin")?;
+            Ok(data)
+        }
+    }
+}
+
+async fn copy(client: &PastebinClient, keys: &Keys, n: u64) -> Result<()> {
+    let entries = client.copy().await?;
+
+    let Some(entry) = entries.iter().rev().nth(n as usize - 1) else {
+        bail!("clipboard holds {} entries, no entry {}", entries.len(), n);
+    };
+
+    let data = if entry.blob || entry.protected {
+        client.content(entry.id).await?.data
+    } else {
+        entry.data.clone().into_bytes()
+    };
+    if entry.encrypted {
+        print_data(&decrypt(keys, &data)?)
+    } else {
+        print_data(&data)
+    }
+}
+
+async fn copy_id(client: &PastebinClient, keys: &Keys, id: Ulid) -> Result<()> {
+    let content = client.content(id).await?;
+    if content.encrypted {
+        print_data(&decrypt(keys, &content.data)?)
+    } else {
+        print_data(&content.data)
+    }
+}
+
+fn decrypt(keys: &Keys, data: &[u8]) -> Result<Vec<u8>> {
+    let data = std::str::from_utf8(data).context("entry is not valid encrypted data")?;
+    crypto::decrypt(&keys.secret(false)?, data)
+}
+
+async fn channels(client: &PastebinClient) -> Result<()> {
+    for channel in client.channels().await? {
+        println!("{}\t{}/{}", channel.name, channel.entries, channel.capacity);
+    }
+    Ok(())
+}
+
+async fn search(client: &PastebinClient, query: &str) -> Result<()> {
+    for hit in client.search(query).await? {
+        let snippet = hit.snippet.replace(['\n', '\t'], " ");
+        println!("{}\t{}\t{}", hit.entry.id, hit.channel, snippet);
+    }
+    Ok(())
+}
+
+fn print_data(data: &[u8]) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(data)?;
+    stdout.flush()?;
+    Ok(())
 }